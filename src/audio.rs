@@ -1,26 +1,80 @@
-use rodio::{self, dynamic_mixer, Source};
-use std::{collections::HashMap, fmt, io::BufReader, path::Path, thread, time::Duration};
+use indexmap::IndexMap;
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, digit1},
+    combinator::{all_consuming, map, map_res, opt},
+    number::complete::double,
+    sequence::terminated,
+    IResult,
+};
+use std::{
+    collections::HashMap,
+    fmt, iter,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::{
-    error::{Error::*, Result},
-    instrumentation::{Instrumentation, SampleFile},
-    pattern::{Amplitude, Pattern, Steps, BEATS_PER_MEASURE, STEPS_PER_MEASURE},
+    display,
+    error::{Error, Error::*, Result},
+    instrumentation::{Binding, Instrumentation, Select},
+    pattern::{Amplitude, Pattern, Steps, Swing, TimeSignature, Velocity},
+    sequencer::{Buffer, Live, Rotation, Schedule, Schedules, Sequencer, Slot},
+    song::Song,
 };
 
 /// Number of playback channels.
-const CHANNELS: u16 = 1;
+/// Mono sample files are panned across both, and stereo sample files keep
+/// their channels.
+const CHANNELS: u16 = 2;
 
 /// Sample rate of playback.
 const SAMPLE_RATE: u32 = 44_100;
 
+/// Frequency of the synthesised click on the first beat of a measure.
+const CLICK_ACCENT_FREQUENCY: f32 = 1_760.0;
+
+/// Frequency of the synthesised click on the other beats of a measure.
+const CLICK_FREQUENCY: f32 = 880.0;
+
+/// Duration of the synthesised click.
+const CLICK_DURATION: Duration = Duration::from_millis(30);
+
+/// Gain of the click on the other beats of a measure relative to the first beat.
+const CLICK_BEAT_GAIN: f32 = 0.6;
+
 /// Represents the playback tempo (beats per minute).
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Tempo(u16);
 
-impl From<u16> for Tempo {
-    #[inline]
-    fn from(v: u16) -> Tempo {
-        Tempo(v)
+impl Tempo {
+    /// Returns a tempo of the beats per minute given, or `None` if the value is
+    /// 0.
+    pub fn new(v: u16) -> Option<Tempo> {
+        if v > 0 {
+            Some(Tempo(v))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the tempo's beats per minute.
+    pub fn value(&self) -> u16 {
+        self.0
+    }
+}
+
+impl FromStr for Tempo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Tempo> {
+        s.parse()
+            .ok()
+            .and_then(Tempo::new)
+            .ok_or_else(|| InvalidTempoError(s.into()))
     }
 }
 
@@ -30,43 +84,531 @@ impl fmt::Display for Tempo {
     }
 }
 
+/// Represents a length of playback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Length {
+    /// A number of seconds.
+    Seconds(f64),
+
+    /// A number of measures.
+    Bars(usize),
+}
+
+impl FromStr for Length {
+    type Err = Error;
+
+    /// Parses a number of seconds (e.g. `30` or `30s`) or bars (e.g. `8bars`).
+    fn from_str(s: &str) -> Result<Length> {
+        match all_consuming(parse_length)(s) {
+            Ok((_, length)) if length.is_positive() => Ok(length),
+            _ => Err(InvalidLengthError(s.into())),
+        }
+    }
+}
+
+impl Length {
+    /// Returns whether the length covers any playback at all.
+    fn is_positive(&self) -> bool {
+        match *self {
+            Length::Seconds(secs) => secs.is_finite() && secs > 0.0,
+            Length::Bars(bars) => bars > 0,
+        }
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Length::Seconds(secs) => write!(f, "{}s", secs),
+            Length::Bars(bars) => write!(f, "{}bars", bars),
+        }
+    }
+}
+
+/// Represents a click track that counts in and keeps time independently of the
+/// pattern. The first beat of each measure is accented.
+#[derive(Debug)]
+pub struct Click {
+    /// Number of measures of click played before the pattern or song.
+    pub count_in: usize,
+
+    /// Whether the click also sounds on every beat throughout playback.
+    pub metronome: bool,
+
+    /// Amplitude of the click.
+    pub level: Amplitude,
+
+    /// Audio file played as the click, or `None` for a synthesised click.
+    pub sample: Option<PathBuf>,
+}
+
+impl Default for Click {
+    fn default() -> Click {
+        Click {
+            count_in: 0,
+            metronome: false,
+            level: Amplitude::max(),
+            sample: None,
+        }
+    }
+}
+
+/// Represents the settings used when playing a pattern or song.
+#[derive(Debug)]
+pub struct PlaySettings {
+    /// Number of times the pattern or song is looped, or `None` to loop until
+    /// stopped.
+    pub loops: Option<u32>,
+
+    /// Length after which playback stops, if any.
+    pub length: Option<Length>,
+
+    /// Click track played along with the pattern or song.
+    pub click: Click,
+
+    /// Whether the pattern grid is shown in the terminal with the playhead
+    /// moving along it.
+    pub playhead: bool,
+}
+
+impl Default for PlaySettings {
+    fn default() -> PlaySettings {
+        PlaySettings {
+            loops: Some(1),
+            length: None,
+            click: Click::default(),
+            playhead: false,
+        }
+    }
+}
+
+/// Represents the settings used when rendering a pattern or song to an audio
+/// file.
+#[derive(Debug)]
+pub struct RenderSettings {
+    /// Number of times the pattern or song is looped, or `None` to loop until
+    /// the length is reached.
+    pub loops: Option<u32>,
+
+    /// Length after which rendering stops, if any.
+    pub length: Option<Length>,
+
+    /// Click track rendered along with the pattern or song.
+    pub click: Click,
+
+    /// Sample rate of the rendered audio.
+    pub sample_rate: u32,
+
+    /// Number of bits per sample of the rendered audio (8, 16, 24 or 32).
+    pub bit_depth: u16,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            loops: Some(1),
+            length: None,
+            click: Click::default(),
+            sample_rate: SAMPLE_RATE,
+            bit_depth: 16,
+        }
+    }
+}
+
 /// A type that represents the fully bound and reduced tracks of a pattern.
-type Tracks = HashMap<SampleFile, (Steps, Amplitude)>;
+type Tracks<'a> = IndexMap<&'a Binding, (Steps, Amplitude)>;
 
-/// Plays a pattern either once or repeatedly at the tempo given using samples
-/// found in the given path.
+/// Represents the sample files shared by a song's patterns, decoded once per
+/// path and shaped once per sample file of a binding, along with the velocity
+/// layers of each binding, so that their rotations carry on across sections.
+#[derive(Default)]
+struct Buffers {
+    decoded: HashMap<PathBuf, Arc<Buffer>>,
+    shaped: HashMap<(Binding, usize), Arc<Buffer>>,
+    layers: HashMap<Binding, Layers>,
+}
+
+/// Plays a pattern or song at the tempo given using samples found in the given
+/// path, looped and cut short per the settings given.
+/// The swing given applies unless a pattern specifies its own.
 pub fn play(
-    pattern: Pattern,
+    song: impl Into<Song>,
     instrumentation: Instrumentation,
     samples_path: &Path,
     tempo: Tempo,
-    repeat: bool,
+    swing: Swing,
+    settings: &PlaySettings,
 ) -> Result<()> {
-    let (tracks, aggregate_steps) = bind_tracks(pattern, instrumentation);
-    let mix = mix_tracks(&tempo, tracks, samples_path)?;
+    check_extent(settings.loops, settings.length)?;
+    let song = song.into();
+    let clicks = Clicks::new(&settings.click, SAMPLE_RATE)?;
+    let sequence = schedule_song(
+        &song,
+        &instrumentation,
+        samples_path,
+        &tempo,
+        swing,
+        clicks.as_ref().filter(|_| settings.click.metronome),
+        SAMPLE_RATE,
+    )?;
+    let count_in =
+        clicks.and_then(|c| c.count_in(&song, settings.click.count_in, &tempo, SAMPLE_RATE));
+    let timeline = Timeline::new(&song, &tempo, swing, count_in.is_some(), SAMPLE_RATE);
+    let schedules = arrange(
+        count_in,
+        sequence,
+        settings.loops,
+        settings.length,
+        SAMPLE_RATE,
+    );
+
+    if let Some(device) = rodio::default_output_device() {
+        let sink = rodio::Sink::new(&device);
+        let sequencer = Sequencer::new(schedules, CHANNELS, SAMPLE_RATE);
+        let clock = sequencer.clock();
+        sink.append(sequencer);
+        if settings.playhead {
+            display::playhead(&song, &timeline, &clock, &tempo, || sink.empty())?;
+        } else {
+            sink.sleep_until_end();
+        }
 
-    if repeat {
-        play_repeat(&tempo, mix, aggregate_steps)
+        Ok(())
     } else {
-        play_once(&tempo, mix)
+        Err(AudioDeviceError())
     }
 }
 
+/// Represents playback of a pattern or song that loops until stopped and whose
+/// mix can be swapped without interrupting it.
+///
+/// Playback stops when the player is dropped.
+pub struct Player {
+    sink: rodio::Sink,
+    slot: Slot,
+    samples_path: PathBuf,
+    tempo: Tempo,
+    swing: Swing,
+    metronome: Option<Clicks>,
+}
+
+impl Player {
+    /// Starts playing a pattern or song until stopped at the tempo given using
+    /// samples found in the given path, after the count-in of the click track
+    /// given. The swing given applies unless a pattern specifies its own.
+    pub fn start(
+        song: impl Into<Song>,
+        instrumentation: Instrumentation,
+        samples_path: &Path,
+        tempo: Tempo,
+        swing: Swing,
+        click: &Click,
+    ) -> Result<Player> {
+        let device = rodio::default_output_device().ok_or(AudioDeviceError())?;
+        let song = song.into();
+        let clicks = Clicks::new(click, SAMPLE_RATE)?;
+        let count_in = clicks
+            .as_ref()
+            .and_then(|c| c.count_in(&song, click.count_in, &tempo, SAMPLE_RATE));
+
+        let mut player = Player {
+            sink: rodio::Sink::new(&device),
+            slot: Slot::default(),
+            samples_path: samples_path.into(),
+            tempo,
+            swing,
+            metronome: clicks.filter(|_| click.metronome),
+        };
+        let live = Live::new(
+            player.schedule(&song, &instrumentation)?,
+            Schedule::new(SAMPLE_RATE as u64),
+        );
+        player.slot = live.slot();
+
+        let schedules: Schedules = Box::new(count_in.map(Arc::new).into_iter().chain(live));
+        player
+            .sink
+            .append(Sequencer::new(schedules, CHANNELS, SAMPLE_RATE));
+
+        Ok(player)
+    }
+
+    /// Sets the tempo of the mixes swapped in from now on.
+    pub fn set_tempo(&mut self, tempo: Tempo) {
+        self.tempo = tempo;
+    }
+
+    /// Replaces the pattern or song and the instrumentation being played.
+    /// The new mix is swapped in at the next measure boundary and carries on
+    /// from the same measure.
+    pub fn swap(&self, song: impl Into<Song>, instrumentation: Instrumentation) -> Result<()> {
+        let sequence = self.schedule(&song.into(), &instrumentation)?;
+        if let Ok(mut slot) = self.slot.lock() {
+            *slot = Some(sequence);
+        }
+
+        Ok(())
+    }
+
+    /// Schedules a song measure by measure.
+    fn schedule(
+        &self,
+        song: &Song,
+        instrumentation: &Instrumentation,
+    ) -> Result<Vec<Arc<Schedule>>> {
+        let sequence = schedule_song(
+            song,
+            instrumentation,
+            &self.samples_path,
+            &self.tempo,
+            self.swing,
+            self.metronome.as_ref(),
+            SAMPLE_RATE,
+        )?;
+
+        Ok(sequence
+            .iter()
+            .flat_map(|(schedule, measures)| schedule.split(*measures))
+            .map(Arc::new)
+            .collect())
+    }
+}
+
+/// Plays a sample file of a binding found in the given path once at the
+/// amplitude given, without waiting for it to finish. The sample file is the
+/// first of those played for a regular step.
+pub fn audition(binding: &Binding, samples_path: &Path, amplitude: Amplitude) -> Result<()> {
+    let device = rodio::default_output_device().ok_or(AudioDeviceError())?;
+    let layers = load_layers(binding, samples_path, SAMPLE_RATE, &mut Buffers::default())?;
+    let mut schedule = Schedule::new(1);
+    schedule.add(
+        0,
+        pick(&layers, Velocity::normal()).clone(),
+        amplitude.value() * decibels(binding.gain()),
+        binding.pan().value(),
+        None,
+    );
+
+    let sink = rodio::Sink::new(&device);
+    sink.append(Sequencer::new(
+        Box::new(iter::once(Arc::new(schedule))),
+        CHANNELS,
+        SAMPLE_RATE,
+    ));
+    sink.detach();
+
+    Ok(())
+}
+
+/// Renders a pattern or song at the tempo given using samples found in the
+/// given path and writes the mix to a WAV file.
+/// The swing given applies unless a pattern specifies its own.
+pub fn render(
+    song: impl Into<Song>,
+    instrumentation: Instrumentation,
+    samples_path: &Path,
+    tempo: Tempo,
+    swing: Swing,
+    output_path: &Path,
+    settings: &RenderSettings,
+) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: CHANNELS,
+        sample_rate: settings.sample_rate,
+        bits_per_sample: settings.bit_depth,
+        sample_format: hound::SampleFormat::Int,
+    };
+    if !matches!(spec.bits_per_sample, 8 | 16 | 24 | 32) {
+        return Err(UnsupportedBitDepthError(spec.bits_per_sample));
+    }
+    if spec.sample_rate == 0 {
+        return Err(UnsupportedSampleRateError(spec.sample_rate));
+    }
+    check_extent(settings.loops, settings.length)?;
+    if settings.loops.is_none() && settings.length.is_none() {
+        return Err(UnboundedRenderError());
+    }
+
+    let song = song.into();
+    let clicks = Clicks::new(&settings.click, settings.sample_rate)?;
+    let sequence = schedule_song(
+        &song,
+        &instrumentation,
+        samples_path,
+        &tempo,
+        swing,
+        clicks.as_ref().filter(|_| settings.click.metronome),
+        settings.sample_rate,
+    )?;
+    let count_in = clicks
+        .and_then(|c| c.count_in(&song, settings.click.count_in, &tempo, settings.sample_rate));
+    let schedules = arrange(
+        count_in,
+        sequence,
+        settings.loops,
+        settings.length,
+        settings.sample_rate,
+    );
+    let mix = Sequencer::new(schedules, CHANNELS, settings.sample_rate);
+
+    // samples are scaled to the full range of the bit depth
+    let scale = ((1_i64 << (spec.bits_per_sample - 1)) - 1) as f32;
+    let mut writer = hound::WavWriter::create(output_path, spec)?;
+    for sample in mix {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * scale).round() as i32)?;
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// A type that represents a schedule along with the number of measures it spans.
+type Measures = (Arc<Schedule>, usize);
+
+/// Checks that a song is looped at least once and cut short, if at all, after
+/// some playback, so that there is something to hear.
+fn check_extent(loops: Option<u32>, length: Option<Length>) -> Result<()> {
+    if loops == Some(0) {
+        return Err(InvalidLoopCountError(0));
+    }
+    match length {
+        Some(length) if !length.is_positive() => Err(InvalidLengthError(length.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the schedules of a song looped the number of times given (or until
+/// stopped) and cut short at the length given, after the count-in given.
+/// Hits past the length are dropped, but the hits before it ring out.
+fn arrange(
+    count_in: Option<Schedule>,
+    sequence: Vec<Measures>,
+    loops: Option<u32>,
+    length: Option<Length>,
+    sample_rate: u32,
+) -> Schedules {
+    let looped: Box<dyn Iterator<Item = Measures> + Send> = match loops {
+        Some(n) => Box::new(iter::repeat(sequence).take(n as usize).flatten()),
+        None => Box::new(sequence.into_iter().cycle()),
+    };
+
+    let schedules: Schedules = match length {
+        None => Box::new(looped.map(|(s, _)| s)),
+        Some(Length::Seconds(secs)) => {
+            let mut left = (secs * sample_rate as f64).round() as u64;
+            Box::new(looped.map_while(move |(s, _)| match left {
+                0 => None,
+                l if l >= s.length() => {
+                    left -= s.length();
+                    Some(s)
+                }
+                l => {
+                    left = 0;
+                    Some(Arc::new(s.truncate(l)))
+                }
+            }))
+        }
+        Some(Length::Bars(bars)) => {
+            let mut left = bars;
+            Box::new(looped.map_while(move |(s, measures)| match left {
+                0 => None,
+                l if l >= measures => {
+                    left -= measures;
+                    Some(s)
+                }
+                l => {
+                    left = 0;
+                    Some(Arc::new(
+                        s.truncate(s.length() * l as u64 / measures as u64),
+                    ))
+                }
+            }))
+        }
+    };
+
+    Box::new(count_in.map(Arc::new).into_iter().chain(schedules))
+}
+
+/// Schedules each of a song's sections and returns the schedules in the order
+/// they are played. Each sample file is decoded once for the whole song.
+/// The metronome clicks given, if any, sound on every beat.
+fn schedule_song(
+    song: &Song,
+    instrumentation: &Instrumentation,
+    samples_path: &Path,
+    tempo: &Tempo,
+    swing: Swing,
+    metronome: Option<&Clicks>,
+    sample_rate: u32,
+) -> Result<Vec<Measures>> {
+    let mut buffers = Buffers::default();
+    let mut sequence = Vec::new();
+    for (pattern, repeats) in song.sections() {
+        let mut schedule = schedule_pattern(
+            pattern,
+            instrumentation,
+            samples_path,
+            tempo,
+            swing,
+            sample_rate,
+            &mut buffers,
+        )?;
+        if let Some(clicks) = metronome {
+            clicks.schedule(
+                &mut schedule,
+                pattern.measures(),
+                pattern.time_signature(),
+                tempo,
+                sample_rate,
+            );
+        }
+        let schedule = Arc::new(schedule);
+        sequence.extend(iter::repeat((schedule, pattern.measures())).take(*repeats));
+    }
+
+    Ok(sequence)
+}
+
+/// Schedules a pattern's steps at exact frame offsets using samples found in
+/// the given path bound per the instrumentation given.
+fn schedule_pattern(
+    pattern: &Pattern,
+    instrumentation: &Instrumentation,
+    samples_path: &Path,
+    tempo: &Tempo,
+    swing: Swing,
+    sample_rate: u32,
+    buffers: &mut Buffers,
+) -> Result<Schedule> {
+    let swing = pattern.swing().unwrap_or(swing);
+    let tracks = bind_tracks(pattern, instrumentation);
+
+    schedule_tracks(
+        pattern,
+        tracks,
+        samples_path,
+        tempo,
+        swing,
+        sample_rate,
+        buffers,
+    )
+}
+
 /// Binds a pattern's step sequences to audio files.
-/// An sequences bound to the same audio file will be unioned.
-/// The smallest amplitude for instruments bound to the same audio file will be used.
-fn bind_tracks(pattern: Pattern, instrumentation: Instrumentation) -> (Tracks, Steps) {
-    let mut aggregate_steps = Steps::zeros();
-    let tracks = instrumentation
+/// An sequences bound to the same audio file with the same parameters will be
+/// unioned. The smallest amplitude for instruments bound alike will be used.
+fn bind_tracks<'a>(pattern: &Pattern, instrumentation: &'a Instrumentation) -> Tracks<'a> {
+    instrumentation
+        .bindings()
         .into_iter()
-        .map(|(sample_file, instruments)| {
+        .map(|(binding, instruments)| {
             let simplified_steps = instruments.iter().fold(
-                (Steps::zeros(), Amplitude::max()),
+                (
+                    Steps::zeros(pattern.steps() * pattern.measures()),
+                    Amplitude::max(),
+                ),
                 |mut acc, instrument| {
                     if let Some((steps, amplitude)) = pattern.get(instrument) {
-                        // update the aggregate step sequence
-                        aggregate_steps.union(steps);
-
                         // update the track's step sequence and amplitude
                         acc.0.union(steps);
                         acc.1 = acc.1.min(amplitude);
@@ -76,102 +618,657 @@ fn bind_tracks(pattern: Pattern, instrumentation: Instrumentation) -> (Tracks, S
                 },
             );
 
-            (sample_file, simplified_steps)
+            (binding, simplified_steps)
         })
-        .collect();
-
-    (tracks, aggregate_steps)
+        .collect()
 }
 
-/// Mixes the tracks together using audio files found in the path given.
-fn mix_tracks(
-    tempo: &Tempo,
+/// Schedules the tracks' steps at exact frame offsets using audio files found
+/// in the path given. The tracks are expected to be bound from the pattern given.
+/// Every second step is delayed by the swing given. Decoded sample files are
+/// kept in the buffers given.
+fn schedule_tracks(
+    pattern: &Pattern,
     tracks: Tracks,
     samples_path: &Path,
-) -> Result<Box<dyn Source<Item = i16> + Send>> {
-    let (controller, mixer) = dynamic_mixer::mixer(CHANNELS, SAMPLE_RATE);
+    tempo: &Tempo,
+    swing: Swing,
+    sample_rate: u32,
+    buffers: &mut Buffers,
+) -> Result<Schedule> {
+    let measure_frames = measure_frames(tempo, pattern.time_signature(), sample_rate);
+    let step_frames = measure_frames / pattern.steps() as f64;
+    let mut schedule = Schedule::new((measure_frames * pattern.measures() as f64).round() as u64);
 
-    for (sample_file, (steps, amplitude)) in tracks.iter() {
-        let sample_file_path = sample_file.with_parent(samples_path)?;
-        let file = std::fs::File::open(sample_file_path.path())?;
-        let source = rodio::Decoder::new(BufReader::new(file))?.buffered();
+    for (binding, (steps, amplitude)) in tracks.iter() {
+        if steps.iter().all(Option::is_none) {
+            continue;
+        }
+        let layers = load_layers(binding, samples_path, sample_rate, buffers)?;
+        let choke: Option<Arc<str>> = binding.choke().map(Arc::from);
+        let level = amplitude.value() * decibels(binding.gain());
 
+        let beats = pattern.time_signature().beats();
         for (i, step) in steps.iter().enumerate() {
-            if !step {
-                continue;
+            if let Some(velocity) = step {
+                let offset = step_offset(i, pattern.steps(), beats, swing);
+                let frame = (step_frames * offset).round() as u64;
+                let gain = level * velocity.value();
+                let pan = binding.pan().value();
+                let rotation = pick(&layers, *velocity).clone();
+                schedule.add(frame, rotation, gain, pan, choke.clone());
             }
-            let delay = step_duration(tempo) * (i as u32);
-            controller.add(source.clone().amplify(amplitude.value()).delay(delay));
         }
     }
 
-    Ok(Box::new(mixer))
+    Ok(schedule)
 }
 
-/// Plays a mixed pattern repeatedly.
-fn play_repeat(
-    tempo: &Tempo,
-    source: Box<dyn Source<Item = i16> + Send>,
-    aggregate_steps: Steps,
-) -> Result<()> {
-    if let Some(device) = rodio::default_output_device() {
-        // compute the amount of trailing silence
-        let trailing_silence = aggregate_steps.trailing_silent_steps();
-
-        // play the pattern
-        rodio::play_raw(
-            &device,
-            source
-                // forward pad with trailing silence
-                .delay(delay_pad_duration(&tempo, trailing_silence))
-                // trim to measure length
-                .take_duration(measure_duration(&tempo))
-                .repeat_infinite()
-                .convert_samples(),
-        );
+/// A type that represents the velocity layers of a binding from the softest,
+/// each as the smallest velocity of the steps it plays and the rotation of its
+/// buffers.
+type Layers = Vec<(f32, Rotation)>;
 
-        // sleep forever
-        thread::park();
+/// Returns the velocity layers of a binding with the buffers of its sample
+/// files found in the path given.
+/// Layers are built once and kept in the buffers given, and the rotations of
+/// the layers returned share their turns with those kept.
+fn load_layers(
+    binding: &Binding,
+    samples_path: &Path,
+    sample_rate: u32,
+    buffers: &mut Buffers,
+) -> Result<Layers> {
+    if let Some(layers) = buffers.layers.get(binding) {
+        return Ok(layers.clone());
+    }
 
-        Ok(())
-    } else {
-        Err(AudioDeviceError())
+    let random = binding.select() == Select::Random;
+    let layers = binding
+        .velocity_layers()
+        .into_iter()
+        .map(|(velocity, indices)| {
+            let layer = indices
+                .into_iter()
+                .map(|n| load_buffer(binding, n, samples_path, sample_rate, buffers))
+                .collect::<Result<Vec<Arc<Buffer>>>>()?;
+
+            Ok((velocity, Rotation::new(layer, random)))
+        })
+        .collect::<Result<Layers>>()?;
+    buffers.layers.insert(binding.clone(), layers.clone());
+
+    Ok(layers)
+}
+
+/// Returns the loudest of the velocity layers given that plays steps of the
+/// velocity given, or the softest layer if none does.
+fn pick<T>(layers: &[(f32, T)], velocity: Velocity) -> &T {
+    let (_, rotation) = layers
+        .iter()
+        .rev()
+        .find(|(v, _)| *v <= velocity.value())
+        .unwrap_or(&layers[0]);
+
+    rotation
+}
+
+/// Returns the buffer of the sample file of the index given of a binding found
+/// in the path given, trimmed, reversed, faded and resampled per the binding.
+/// Buffers are decoded and shaped once and kept in the buffers given.
+fn load_buffer(
+    binding: &Binding,
+    layer: usize,
+    samples_path: &Path,
+    sample_rate: u32,
+    buffers: &mut Buffers,
+) -> Result<Arc<Buffer>> {
+    let key = (binding.clone(), layer);
+    if let Some(buffer) = buffers.shaped.get(&key) {
+        return Ok(buffer.clone());
     }
+
+    let sample_file = binding.layers()[layer].sample_file();
+    let path = sample_file.with_parent(samples_path)?.0;
+    let decoded = match buffers.decoded.get(&path) {
+        Some(buffer) => buffer.clone(),
+        None => {
+            let buffer = Arc::new(Buffer::decode(&path, CHANNELS, sample_rate)?);
+            buffers.decoded.insert(path, buffer.clone());
+            buffer
+        }
+    };
+
+    // times are of the sample file as decoded, before any pitch shift
+    let frames = |ms: f32| (ms / 1_000.0 * sample_rate as f32).round() as usize;
+    let mut buffer = decoded.trim(frames(binding.start()), binding.end().map(frames), CHANNELS);
+    if binding.reverse() {
+        buffer = buffer.reverse(CHANNELS);
+    }
+    buffer = buffer.envelope(
+        frames(binding.attack()),
+        frames(binding.decay()),
+        frames(binding.release()),
+        CHANNELS,
+    );
+    if binding.rate() != 1.0 {
+        buffer = buffer.resample(binding.rate(), CHANNELS);
+    }
+    let buffer = Arc::new(buffer);
+    buffers.shaped.insert(key, buffer.clone());
+
+    Ok(buffer)
 }
 
-/// Plays a mixed pattern once.
-fn play_once(tempo: &Tempo, source: Box<dyn Source<Item = i16> + Send>) -> Result<()> {
-    if let Some(device) = rodio::default_output_device() {
-        // play the pattern
-        rodio::play_raw(&device, source.convert_samples());
+/// Returns the linear gain of the decibels given.
+fn decibels(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
 
-        // sleep for the duration of a single measure
-        thread::sleep(measure_duration(tempo));
+/// Represents a position of the playhead within a song.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Position {
+    /// The playhead is in the count-in.
+    CountIn,
 
-        Ok(())
-    } else {
-        Err(AudioDeviceError())
+    /// The playhead is at a step, counted across the measures of a section's
+    /// pattern, in a repetition of the section. All are counted from 0.
+    Step {
+        section: usize,
+        repeat: usize,
+        step: usize,
+    },
+}
+
+/// Represents where the schedules of a song fall within its sections, to
+/// follow the playhead of its playback.
+pub(crate) struct Timeline {
+    /// Whether the first schedule is a count-in.
+    count_in: bool,
+
+    /// The section and repetition of each schedule of the song, in order.
+    schedules: Vec<(usize, usize)>,
+
+    /// The frame offset of each step of each section's pattern.
+    steps: Vec<Vec<u64>>,
+}
+
+impl Timeline {
+    /// Returns the timeline of a song played at the tempo and sample rate
+    /// given, after a count-in if there is one.
+    /// The swing given applies unless a pattern specifies its own.
+    fn new(song: &Song, tempo: &Tempo, swing: Swing, count_in: bool, sample_rate: u32) -> Timeline {
+        let schedules = song
+            .sections()
+            .enumerate()
+            .flat_map(|(s, (_, repeats))| (0..*repeats).map(move |r| (s, r)))
+            .collect();
+        let steps = song
+            .sections()
+            .map(|(pattern, _)| {
+                let swing = pattern.swing().unwrap_or(swing);
+                let measure_frames = measure_frames(tempo, pattern.time_signature(), sample_rate);
+                let step_frames = measure_frames / pattern.steps() as f64;
+                let beats = pattern.time_signature().beats();
+                (0..pattern.steps() * pattern.measures())
+                    .map(|i| {
+                        let offset = step_offset(i, pattern.steps(), beats, swing);
+                        (step_frames * offset).round() as u64
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Timeline {
+            count_in,
+            schedules,
+            steps,
+        }
+    }
+
+    /// Returns the position of the playhead at the frame given of the schedule
+    /// given, counting schedules from the start of playback.
+    pub fn locate(&self, (schedule, frame): (usize, u64)) -> Option<Position> {
+        let schedule = match (self.count_in, schedule) {
+            (true, 0) => return Some(Position::CountIn),
+            (true, s) => s - 1,
+            (false, s) => s,
+        };
+        if self.schedules.is_empty() {
+            return None;
+        }
+
+        let (section, repeat) = self.schedules[schedule % self.schedules.len()];
+        let steps = &self.steps[section];
+        let step = steps.partition_point(|f| *f <= frame).checked_sub(1)?;
+
+        Some(Position::Step {
+            section,
+            repeat,
+            step,
+        })
     }
 }
 
-/// Computes the duration of a measure.
-fn measure_duration(tempo: &Tempo) -> Duration {
-    Duration::from_secs_f32(1.0 / (tempo.0 as f32 / 60.0 / BEATS_PER_MEASURE as f32))
+/// Represents the clicks of a click track ready to be scheduled.
+struct Clicks {
+    /// The click on the first beat of a measure.
+    accent: Arc<Buffer>,
+
+    /// The click on the other beats of a measure.
+    beat: Arc<Buffer>,
+
+    /// The gain of the click on the first beat of a measure.
+    gain: f32,
 }
 
-/// Computes the duration of a step.
-fn step_duration(tempo: &Tempo) -> Duration {
-    measure_duration(tempo) / STEPS_PER_MEASURE as u32
+impl Clicks {
+    /// Returns the clicks of the click track given at the sample rate given, or
+    /// `None` if the click track is neither counted in nor a metronome.
+    fn new(click: &Click, sample_rate: u32) -> Result<Option<Clicks>> {
+        if click.count_in == 0 && !click.metronome {
+            return Ok(None);
+        }
+
+        let (accent, beat) = match &click.sample {
+            Some(p) if !p.is_file() => return Err(FileDoesNotExistError(p.clone())),
+            Some(p) => {
+                let buffer = Arc::new(Buffer::decode(p, CHANNELS, sample_rate)?);
+                (buffer.clone(), buffer)
+            }
+            None => (
+                Arc::new(Buffer::tone(
+                    CLICK_ACCENT_FREQUENCY,
+                    CLICK_DURATION,
+                    CHANNELS,
+                    sample_rate,
+                )),
+                Arc::new(Buffer::tone(
+                    CLICK_FREQUENCY,
+                    CLICK_DURATION,
+                    CHANNELS,
+                    sample_rate,
+                )),
+            ),
+        };
+
+        Ok(Some(Clicks {
+            accent,
+            beat,
+            gain: click.level.value(),
+        }))
+    }
+
+    /// Adds a click on every beat of the number of measures given to a schedule.
+    fn schedule(
+        &self,
+        schedule: &mut Schedule,
+        measures: usize,
+        time_signature: TimeSignature,
+        tempo: &Tempo,
+        sample_rate: u32,
+    ) {
+        let beats = time_signature.beats();
+        let beat_frames = measure_frames(tempo, time_signature, sample_rate) / beats as f64;
+        for beat in 0..measures * beats {
+            let frame = (beat_frames * beat as f64).round() as u64;
+            if beat % beats == 0 {
+                schedule.add(frame, self.accent.clone(), self.gain, 0.0, None);
+            } else {
+                schedule.add(
+                    frame,
+                    self.beat.clone(),
+                    self.gain * CLICK_BEAT_GAIN,
+                    0.0,
+                    None,
+                );
+            }
+        }
+    }
+
+    /// Returns a schedule that counts in the number of measures given in the
+    /// time signature of the song's first section, or `None` if there are no
+    /// measures to count in.
+    fn count_in(
+        &self,
+        song: &Song,
+        measures: usize,
+        tempo: &Tempo,
+        sample_rate: u32,
+    ) -> Option<Schedule> {
+        if measures == 0 {
+            return None;
+        }
+
+        let time_signature = song
+            .sections()
+            .next()
+            .map(|(pattern, _)| pattern.time_signature())
+            .unwrap_or_default();
+        let length = measure_frames(tempo, time_signature, sample_rate) * measures as f64;
+        let mut schedule = Schedule::new(length.round() as u64);
+        self.schedule(&mut schedule, measures, time_signature, tempo, sample_rate);
+
+        Some(schedule)
+    }
+}
+
+/// Parses a length of playback.
+fn parse_length(s: &str) -> IResult<&str, Length> {
+    alt((
+        map(
+            terminated(map_res(digit1, str::parse), alt((tag("bars"), tag("bar")))),
+            Length::Bars,
+        ),
+        map(terminated(double, opt(char('s'))), Length::Seconds),
+    ))(s)
+}
+
+/// Computes the offset of a step in (fractional) steps, counted across
+/// measures of the number of steps given with the number of beats given.
+/// Steps in odd positions within their beat are delayed by the swing given.
+/// Measures whose steps do not split evenly into beats are not swung.
+fn step_offset(i: usize, steps: usize, beats: usize, swing: Swing) -> f64 {
+    let beat_steps = if beats > 0 && steps % beats == 0 {
+        steps / beats
+    } else {
+        1
+    };
+    if (i % steps) % beat_steps % 2 == 1 {
+        i as f64 + swing.delay() as f64
+    } else {
+        i as f64
+    }
 }
 
-/// Computes the duration to delay a mix with trailing silence when played on repeat.
-/// This is necessary so that playback of the next iteration begins at the end
-/// of the current iteration's measure instead of after its final non-silent step.
-fn delay_pad_duration(tempo: &Tempo, trailing_silent_steps: usize) -> Duration {
-    step_duration(tempo).mul_f32(delay_factor(tempo)) * trailing_silent_steps as u32
+/// Computes the (fractional) number of frames in a measure.
+/// The tempo is counted in quarter notes regardless of the time signature.
+fn measure_frames(tempo: &Tempo, time_signature: TimeSignature, sample_rate: u32) -> f64 {
+    let quarter_frames = sample_rate as f64 * 60.0 / tempo.0 as f64;
+    quarter_frames * time_signature.beats() as f64 * 4.0 / time_signature.unit() as f64
 }
 
-/// Computes a factor necessary for delay-padding a mix played on repeat.
-fn delay_factor(tempo: &Tempo) -> f32 {
-    -1.0 / 120.0 * tempo.0 as f32 + 2.0
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Instrument;
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_measure_frames() {
+        let common = TimeSignature::default();
+
+        assert_eq!(measure_frames(&Tempo(120), common, 44_100), 88_200.0);
+        assert_eq!(measure_frames(&Tempo(60), common, 48_000), 192_000.0);
+        assert_eq!(measure_frames(&Tempo(300), common, 44_100), 35_280.0);
+        assert_eq!(measure_frames(&Tempo(140), common, 44_100), 75_600.0);
+        assert_eq!(
+            measure_frames(&Tempo(120), TimeSignature::new(3, 4).unwrap(), 44_100),
+            66_150.0
+        );
+        assert_eq!(
+            measure_frames(&Tempo(120), TimeSignature::new(7, 8).unwrap(), 48_000),
+            84_000.0
+        );
+        assert_eq!(
+            measure_frames(&Tempo(60), TimeSignature::new(6, 8).unwrap(), 48_000),
+            144_000.0
+        );
+    }
+
+    #[test]
+    fn test_tempo() {
+        assert_eq!("120".parse::<Tempo>().unwrap(), Tempo(120));
+        assert_eq!(Tempo::new(0), None);
+        assert!(matches!("0".parse::<Tempo>(), Err(InvalidTempoError(_))));
+        assert!("-1".parse::<Tempo>().is_err());
+        assert!("fast".parse::<Tempo>().is_err());
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!("30".parse::<Length>().unwrap(), Length::Seconds(30.0));
+        assert_eq!("2.5s".parse::<Length>().unwrap(), Length::Seconds(2.5));
+        assert_eq!("8bars".parse::<Length>().unwrap(), Length::Bars(8));
+        assert_eq!("1bar".parse::<Length>().unwrap(), Length::Bars(1));
+        assert!("-1s".parse::<Length>().is_err());
+        assert!("0".parse::<Length>().is_err());
+        assert!("0bars".parse::<Length>().is_err());
+        assert!("8 bars".parse::<Length>().is_err());
+        assert!("2.5bars".parse::<Length>().is_err());
+        assert!("".parse::<Length>().is_err());
+    }
+
+    #[test]
+    fn test_arrange() {
+        let sequence = vec![
+            (Arc::new(Schedule::new(200)), 2),
+            (Arc::new(Schedule::new(100)), 1),
+        ];
+        let lengths = |loops, length| -> Vec<u64> {
+            arrange(None, sequence.clone(), loops, length, 100)
+                .map(|s| s.length())
+                .collect()
+        };
+
+        assert_eq!(lengths(Some(2), None), vec![200, 100, 200, 100]);
+        assert_eq!(lengths(Some(2), Some(Length::Bars(4))), vec![200, 100, 100]);
+        assert_eq!(lengths(Some(1), Some(Length::Bars(8))), vec![200, 100]);
+        assert_eq!(lengths(Some(2), Some(Length::Seconds(2.5))), vec![200, 50]);
+        assert_eq!(
+            lengths(None, Some(Length::Bars(7))),
+            vec![200, 100, 200, 100, 100]
+        );
+    }
+
+    #[test]
+    fn test_clicks() {
+        let click = Click {
+            count_in: 2,
+            ..Click::default()
+        };
+        let clicks = Clicks::new(&click, 44_100).unwrap().unwrap();
+        let mut pattern = Pattern::default();
+        pattern
+            .set_time_signature(TimeSignature::new(3, 4).unwrap())
+            .unwrap();
+        let song = Song::from(pattern);
+
+        assert!(Clicks::new(&Click::default(), 44_100).unwrap().is_none());
+        assert!(clicks.count_in(&song, 0, &Tempo(120), 44_100).is_none());
+        assert_eq!(
+            clicks
+                .count_in(&song, 2, &Tempo(120), 44_100)
+                .map(|s| s.length()),
+            Some(132_300)
+        );
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mut song = Song::new();
+        song.push(Pattern::new(4, 2, TimeSignature::default()).unwrap(), 2);
+        song.push(Pattern::new(4, 1, TimeSignature::default()).unwrap(), 1);
+        let timeline = Timeline::new(&song, &Tempo(60), Swing::default(), true, 4);
+
+        assert_eq!(timeline.locate((0, 3)), Some(Position::CountIn));
+        assert_eq!(
+            timeline.locate((1, 0)),
+            Some(Position::Step {
+                section: 0,
+                repeat: 0,
+                step: 0
+            })
+        );
+        assert_eq!(
+            timeline.locate((2, 31)),
+            Some(Position::Step {
+                section: 0,
+                repeat: 1,
+                step: 7
+            })
+        );
+        assert_eq!(
+            timeline.locate((6, 4)),
+            Some(Position::Step {
+                section: 1,
+                repeat: 0,
+                step: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_pick() {
+        let layers = vec![(0.2, "soft"), (0.5, "medium"), (0.9, "hard")];
+
+        assert_eq!(*pick(&layers, Velocity::new(0.1).unwrap()), "soft");
+        assert_eq!(*pick(&layers, Velocity::new(0.5).unwrap()), "medium");
+        assert_eq!(*pick(&layers, Velocity::new(0.8).unwrap()), "medium");
+        assert_eq!(*pick(&layers, Velocity::new(1.0).unwrap()), "hard");
+    }
+
+    #[test]
+    fn test_load_layers() {
+        let instrumentation =
+            Instrumentation::read("snare sd.wav,kick.wav\n".as_bytes(), Path::new("test")).unwrap();
+        let binding = instrumentation.get(&Instrument::from("snare")).unwrap();
+        let samples = Path::new("assets/samples/linndrum");
+        let mut buffers = Buffers::default();
+        let mut load = || load_layers(binding, samples, 8_000, &mut buffers).unwrap();
+
+        // the layers of every section take turns together
+        let (first, second) = (load(), load());
+        let snare = first[0].1.next();
+        assert!(!Arc::ptr_eq(&snare, &second[0].1.next()));
+        assert!(Arc::ptr_eq(&snare, &first[0].1.next()));
+    }
+
+    #[test]
+    fn test_render() {
+        let pattern = Pattern::read("kick |x---|\n".as_bytes(), Path::new("test")).unwrap();
+        let instrumentation =
+            Instrumentation::read("kick kick.wav\n".as_bytes(), Path::new("test")).unwrap();
+        let samples = Path::new("assets/samples/linndrum");
+        let output = env::temp_dir().join(format!("rudiments-render-{}.wav", process::id()));
+        let tempo = Tempo::new(120).unwrap();
+        let render = |loops, length, sample_rate, bit_depth| -> Result<()> {
+            let settings = RenderSettings {
+                loops,
+                length,
+                sample_rate,
+                bit_depth,
+                ..RenderSettings::default()
+            };
+            render(
+                pattern.clone(),
+                instrumentation.clone(),
+                samples,
+                tempo,
+                Swing::default(),
+                &output,
+                &settings,
+            )
+        };
+        let read = || {
+            let reader = hound::WavReader::open(&output).unwrap();
+            let read = (reader.spec(), reader.duration());
+            fs::remove_file(&output).unwrap();
+            read
+        };
+
+        render(Some(2), None, 8_000, 24).unwrap();
+        let (spec, frames) = read();
+        assert_eq!(spec.channels, CHANNELS);
+        assert_eq!(spec.sample_rate, 8_000);
+        assert_eq!(spec.bits_per_sample, 24);
+        // two loops of a measure of 2 seconds, with the kick done before the end
+        assert_eq!(frames, 32_000);
+
+        // a duration without a number of loops loops for as long as it takes
+        render(None, Some(Length::Bars(8)), 8_000, 16).unwrap();
+        assert_eq!(read().1, 128_000);
+        render(Some(2), Some(Length::Bars(8)), 8_000, 16).unwrap();
+        assert_eq!(read().1, 32_000);
+
+        let invalid = [
+            (Some(1), None, 0, 16),
+            (Some(1), None, 8_000, 12),
+            (Some(0), None, 8_000, 16),
+            (Some(1), Some(Length::Bars(0)), 8_000, 16),
+            (None, None, 8_000, 16),
+        ];
+        for (loops, length, sample_rate, bit_depth) in invalid {
+            assert!(matches!(
+                render(loops, length, sample_rate, bit_depth),
+                Err(UnsupportedSampleRateError(0))
+                    | Err(UnsupportedBitDepthError(12))
+                    | Err(InvalidLoopCountError(0))
+                    | Err(InvalidLengthError(_))
+                    | Err(UnboundedRenderError())
+            ));
+        }
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_render_missing_sample() {
+        let pattern =
+            Pattern::read("kick  |x---|\nsnare |--x-|\n".as_bytes(), Path::new("test")).unwrap();
+        let mut instrumentation = Instrumentation::read(
+            "kick kick.wav\nsnare missing.wav\n".as_bytes(),
+            Path::new("test"),
+        )
+        .unwrap();
+        let samples = Path::new("assets/samples/linndrum");
+        let output = env::temp_dir().join(format!("rudiments-missing-{}.wav", process::id()));
+        let render = |instrumentation: &Instrumentation| {
+            render(
+                pattern.clone(),
+                instrumentation.clone(),
+                samples,
+                Tempo::new(120).unwrap(),
+                Swing::default(),
+                &output,
+                &RenderSettings::default(),
+            )
+        };
+
+        assert!(matches!(
+            render(&instrumentation),
+            Err(FileDoesNotExistError(_))
+        ));
+
+        // the missing sample is left out and the rest is still rendered
+        instrumentation.remove_missing(samples);
+        render(&instrumentation).unwrap();
+        let frames = hound::WavReader::open(&output).unwrap().duration();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(frames, 88_200);
+    }
+
+    #[test]
+    fn test_step_offset() {
+        let straight = Swing::default();
+        let shuffle = Swing::new(50.0).unwrap();
+
+        assert_eq!(step_offset(0, 8, 4, straight), 0.0);
+        assert_eq!(step_offset(1, 8, 4, straight), 1.0);
+        assert_eq!(step_offset(0, 8, 4, shuffle), 0.0);
+        assert_eq!(step_offset(1, 8, 4, shuffle), 1.5);
+        assert_eq!(step_offset(2, 8, 4, shuffle), 2.0);
+        assert_eq!(step_offset(7, 8, 4, shuffle), 7.5);
+
+        // triplets swing the middle step of each beat, never a downbeat
+        let triplets: Vec<f64> = (0..6).map(|i| step_offset(i, 12, 4, shuffle)).collect();
+        assert_eq!(triplets, vec![0.0, 1.5, 2.0, 3.0, 4.5, 5.0]);
+
+        // each measure starts on the beat, even with an odd number of steps
+        let waltz: Vec<f64> = (0..12).map(|i| step_offset(i, 6, 3, shuffle)).collect();
+        assert_eq!(waltz[6..9], [6.0, 7.5, 8.0]);
+        assert_eq!(step_offset(9, 6, 3, shuffle), 9.5);
+        assert_eq!(step_offset(5, 5, 4, shuffle), 5.0);
+        assert_eq!(step_offset(6, 5, 4, shuffle), 6.0);
+        assert_eq!(step_offset(7, 5, 4, shuffle), 7.0);
+    }
 }
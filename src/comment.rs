@@ -0,0 +1,56 @@
+/// Indicates the start of a comment in a pattern or instrumentation file.
+pub const COMMENT: char = '#';
+
+/// Splits a line of a pattern or instrumentation file into its contents and
+/// the text of its comment, if it has one.
+/// A comment starts at the first comment character and runs to the end of the line.
+pub fn split(l: &str) -> (&str, Option<&str>) {
+    match l.find(COMMENT) {
+        Some(i) => (&l[..i], Some(&l[i + COMMENT.len_utf8()..])),
+        None => (l, None),
+    }
+}
+
+/// Returns the first word of a line if a comment starts within it, which cuts
+/// the word short. A line's first word names an instrument, so this tells an
+/// instrument containing the comment character apart from a comment.
+pub fn within_first_word(l: &str) -> Option<&str> {
+    l.split_whitespace()
+        .next()
+        .filter(|w| w.contains(COMMENT) && !w.starts_with(COMMENT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let s1 = "";
+        let s2 = "a b";
+        let s3 = "# a";
+        let s4 = "a b # c";
+        let s5 = "a b#";
+        let s6 = "a # b # c";
+
+        assert_eq!(split(s1), ("", None));
+        assert_eq!(split(s2), ("a b", None));
+        assert_eq!(split(s3), ("", Some(" a")));
+        assert_eq!(split(s4), ("a b ", Some(" c")));
+        assert_eq!(split(s5), ("a b", Some("")));
+        assert_eq!(split(s6), ("a ", Some(" b # c")));
+    }
+
+    #[test]
+    fn test_within_first_word() {
+        let s1 = "hi#hat |x---|";
+        let s2 = "  a# b";
+        let s3 = "# a#b";
+        let s4 = "a |x---|# b";
+
+        assert_eq!(within_first_word(s1), Some("hi#hat"));
+        assert_eq!(within_first_word(s2), Some("a#"));
+        assert_eq!(within_first_word(s3), None);
+        assert_eq!(within_first_word(s4), None);
+    }
+}
@@ -0,0 +1,218 @@
+use nom::{character::complete::space0, error::ErrorKind, IResult};
+use std::{
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// A type that represents the result of parsing part of a line.
+pub type ParseResult<'a, O> = IResult<&'a str, O, Failure<'a>>;
+
+/// Represents a failure to parse part of a line.
+#[derive(Debug, PartialEq)]
+pub struct Failure<'a> {
+    /// The part of the line that could not be parsed.
+    pub span: &'a str,
+
+    /// The reason the span could not be parsed, if known.
+    pub reason: Option<String>,
+}
+
+impl<'a> nom::error::ParseError<&'a str> for Failure<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Failure<'a> {
+        let len = input.chars().next().map_or(0, char::len_utf8);
+        Failure {
+            span: &input[..len],
+            reason: None,
+        }
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Failure<'a>) -> Failure<'a> {
+        other
+    }
+}
+
+/// Returns an unrecoverable failure to parse the span given for the reason given.
+pub fn fail<'a, O>(span: &'a str, reason: impl Into<String>) -> ParseResult<'a, O> {
+    Err(nom::Err::Failure(Failure {
+        span,
+        reason: Some(reason.into()),
+    }))
+}
+
+/// Parses the end of a line that must not hold anything but whitespace after
+/// the item described.
+pub fn parse_end<'a>(s: &'a str, item: &str) -> ParseResult<'a, ()> {
+    let (s, _) = space0(s)?;
+    if s.is_empty() {
+        Ok((s, ()))
+    } else {
+        let span = s.trim_end();
+        fail(span, format!("unexpected `{}` after {}", span, item))
+    }
+}
+
+/// Returns the byte range a part of a line occupies within the line.
+/// The part must be a slice of the line.
+pub fn span_of(line: &str, part: &str) -> Range<usize> {
+    let start = part.as_ptr() as usize - line.as_ptr() as usize;
+    start..start + part.len()
+}
+
+/// Describes where and why a line of a structured file failed to parse.
+#[derive(Debug)]
+pub struct Diagnostic {
+    path: PathBuf,
+    line: usize,
+    column: usize,
+    width: usize,
+    source: String,
+    reason: String,
+}
+
+impl Diagnostic {
+    /// Returns a diagnostic for the byte range given of a line of the file
+    /// located at the path given. Lines are numbered from 1.
+    pub fn new(
+        path: &Path,
+        number: usize,
+        line: &str,
+        span: Range<usize>,
+        reason: impl Into<String>,
+    ) -> Diagnostic {
+        let start = span.start.min(line.len());
+        let end = span.end.max(start).min(line.len());
+
+        Diagnostic {
+            path: path.into(),
+            line: number,
+            column: line[..start].chars().count() + 1,
+            width: line[start..end].chars().count().max(1),
+            source: line.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns a diagnostic for a failure to parse a line of the file located
+    /// at the path given. The reason given is used if the failure has none.
+    pub fn from_failure(
+        path: &Path,
+        number: usize,
+        line: &str,
+        err: nom::Err<Failure>,
+        reason: &str,
+    ) -> Diagnostic {
+        match err {
+            nom::Err::Error(f) | nom::Err::Failure(f) => {
+                let reason = f.reason.unwrap_or_else(|| reason.into());
+                Diagnostic::new(path, number, line, span_of(line, f.span), reason)
+            }
+            nom::Err::Incomplete(_) => Diagnostic::new(path, number, line, 0..line.len(), reason),
+        }
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Returns the number of the line, counting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the column the diagnostic starts at, counting from 1.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns the reason the line failed to parse.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        // keep tabs so that the caret lines up with the source
+        let indent: String = self
+            .source
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(f, "{}", self.reason)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter,
+            self.path.display(),
+            self.line,
+            self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source)?;
+        write!(f, "{} | {}{}", gutter, indent, "^".repeat(self.width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_end() {
+        let s1 = "";
+        let s2 = "  ";
+        let s3 = " x ";
+
+        assert_eq!(parse_end(s1, "a").unwrap(), ("", ()));
+        assert_eq!(parse_end(s2, "a").unwrap(), ("", ()));
+        assert_eq!(
+            parse_end(s3, "a"),
+            Err(nom::Err::Failure(Failure {
+                span: "x",
+                reason: Some("unexpected `x` after a".into())
+            }))
+        );
+    }
+
+    #[test]
+    fn test_span_of() {
+        let l = "kick |x---| 0.5";
+
+        assert_eq!(span_of(l, &l[5..11]), 5..11);
+        assert_eq!(span_of(l, &l[15..]), 15..15);
+    }
+
+    #[test]
+    fn test_diagnostic_display() {
+        let l = "snare |----|x---|----|x--|";
+        let d = Diagnostic::new(Path::new("p"), 12, l, 6..26, "15 steps, expected 16");
+
+        assert_eq!((d.line, d.column), (12, 7));
+        assert_eq!(
+            d.to_string(),
+            "15 steps, expected 16\n  \
+             --> p:12:7\n   \
+             |\n\
+             12 | snare |----|x---|----|x--|\n   \
+             |       ^^^^^^^^^^^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_from_failure() {
+        let l = "a\tb c";
+        let e = nom::Err::Failure(Failure {
+            span: &l[4..],
+            reason: None,
+        });
+        let d = Diagnostic::from_failure(Path::new("p"), 1, l, e, "bad");
+
+        assert_eq!((d.column, d.reason.as_str()), (5, "bad"));
+        assert!(d.to_string().ends_with("1 | a\tb c\n  |  \t  ^"));
+    }
+}
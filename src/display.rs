@@ -0,0 +1,196 @@
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyModifiers},
+    queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use crate::{
+    audio::{Position, Tempo, Timeline},
+    error::Result,
+    pattern::{Pattern, Steps},
+    sequencer::Clock,
+    song::Song,
+};
+
+/// Interval at which the playhead is checked for a move.
+const REFRESH: Duration = Duration::from_millis(10);
+
+/// Switches the terminal to a blank screen that takes keys one at a time.
+pub fn open(w: &mut impl Write) -> io::Result<()> {
+    terminal::enable_raw_mode()?;
+    queue!(w, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+    w.flush()
+}
+
+/// Restores the terminal switched by `open`.
+pub fn close(w: &mut impl Write) -> io::Result<()> {
+    queue!(w, cursor::Show, terminal::LeaveAlternateScreen)?;
+    w.flush()?;
+
+    terminal::disable_raw_mode()
+}
+
+/// Draws the tracks of a pattern as a grid of steps from the line given, with
+/// the steps for which `highlight` holds of the track and step shown in
+/// reverse. Returns the line after the grid.
+pub fn grid(
+    w: &mut impl Write,
+    pattern: &Pattern,
+    line: u16,
+    highlight: impl Fn(usize, usize) -> bool,
+) -> io::Result<u16> {
+    let width = pattern
+        .instruments()
+        .map(|i| i.to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+    // steps are grouped into beats as in the pattern file
+    let beats = pattern.time_signature().beats();
+    let beat = if pattern.steps() % beats == 0 {
+        pattern.steps() / beats
+    } else {
+        pattern.steps()
+    };
+
+    let mut line = line;
+    for (r, i) in pattern.instruments().enumerate() {
+        let (steps, amplitude) = match pattern.get(i) {
+            Some(track) => track,
+            None => continue,
+        };
+        queue!(
+            w,
+            cursor::MoveTo(0, line),
+            Print(format!("{:width$} ", i.to_string(), width = width))
+        )?;
+        for (c, step) in steps.iter().enumerate() {
+            if c % beat == 0 {
+                queue!(w, Print('|'))?;
+            }
+            let cell = Steps::from(vec![*step]).to_string();
+            if highlight(r, c) {
+                queue!(
+                    w,
+                    SetAttribute(Attribute::Reverse),
+                    Print(cell),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(w, Print(cell))?;
+            }
+        }
+        queue!(w, Print(format!("| {}", amplitude)))?;
+        line += 1;
+    }
+
+    Ok(line)
+}
+
+/// Shows the grid of the pattern being played with the playhead moving along
+/// it, followed by the current bar and the tempo, until `done` holds or `q` is
+/// pressed. The playhead follows the clock of the song's playback.
+pub fn playhead(
+    song: &Song,
+    timeline: &Timeline,
+    clock: &Clock,
+    tempo: &Tempo,
+    done: impl Fn() -> bool,
+) -> Result<()> {
+    let mut stdout = io::stdout();
+    open(&mut stdout)?;
+
+    let result = (|| -> Result<()> {
+        let mut shown = None;
+        while !done() {
+            if event::poll(REFRESH)? {
+                if let Event::Key(key) = event::read()? {
+                    let interrupt = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if interrupt || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                        break;
+                    }
+                }
+            }
+
+            let position = timeline.locate(clock.read());
+            if position != shown {
+                draw(&mut stdout, song, position.as_ref(), tempo)?;
+                shown = position;
+            }
+        }
+
+        Ok(())
+    })();
+
+    close(&mut stdout)?;
+
+    result
+}
+
+/// Draws the pattern at the position given with its step highlighted.
+fn draw(
+    w: &mut impl Write,
+    song: &Song,
+    position: Option<&Position>,
+    tempo: &Tempo,
+) -> io::Result<()> {
+    queue!(w, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+
+    let (section, repeat, step) = match position {
+        Some(Position::Step {
+            section,
+            repeat,
+            step,
+        }) => (*section, *repeat, *step),
+        Some(Position::CountIn) => {
+            queue!(w, Print(format!("count-in  tempo {}", tempo)))?;
+            return w.flush();
+        }
+        None => return w.flush(),
+    };
+    let (pattern, repeats) = match song.sections().nth(section) {
+        Some(s) => s,
+        None => return w.flush(),
+    };
+
+    let bar = repeat * pattern.measures() + step / pattern.steps() + 1;
+    let bars = repeats * pattern.measures();
+    let mut status = format!("bar {}/{}  tempo {}", bar, bars, tempo);
+    let sections = song.sections().len();
+    if sections > 1 {
+        status = format!("section {}/{}  {}", section + 1, sections, status);
+    }
+    queue!(w, Print(status))?;
+
+    grid(w, pattern, 2, |_, c| c == step)?;
+
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_grid() {
+        let s = "time 3/4\n\
+                 hi-hat |x-|x-|x-| 0.5\n\
+                 kick   |x-|--|--|\n";
+        let p = Pattern::read(s.as_bytes(), Path::new("test")).unwrap();
+        let mut w = Vec::new();
+        let line = grid(&mut w, &p, 2, |r, c| (r, c) == (1, 0)).unwrap();
+        let screen = String::from_utf8(w).unwrap();
+
+        assert_eq!(line, 4);
+        assert!(screen.contains("hi-hat |x-|x-|x-| 0.5"));
+        assert!(screen.contains("kick   |\u{1b}[7mx\u{1b}[0m-|--|--| 1"));
+    }
+}
@@ -0,0 +1,400 @@
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    queue,
+    style::Print,
+    terminal::{self, ClearType},
+};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    audio::{self, Click, Player, Tempo},
+    display,
+    error::Result,
+    instrumentation::{Instrumentation, Problem},
+    pattern::{Amplitude, Instrument, Pattern, Swing},
+};
+
+/// Amount by which the amplitude of a track is adjusted.
+const AMPLITUDE_STEP: f32 = 0.1;
+
+/// Amount by which the tempo is adjusted.
+const TEMPO_STEP: u16 = 1;
+
+/// Keys of the editor, shown below the grid.
+const HELP: &str =
+    "arrows move  space toggle  +/- amplitude  </> tempo  a audition  s save  q quit";
+
+/// Edits the pattern file located at the path given in an interactive step
+/// sequencer while the pattern loops at the tempo given using samples found in
+/// the given path. Every edit is heard from the next measure on.
+///
+/// The grid shows a row of steps per instrument. The arrow keys move between
+/// steps, space toggles a step, `+` and `-` adjust the amplitude of a track,
+/// `<` and `>` adjust the tempo, `a` auditions a track's sample, `s` saves the
+/// pattern back to its file, and `q` quits.
+pub fn edit(
+    path: &Path,
+    instrumentation: Instrumentation,
+    samples_path: &Path,
+    tempo: Tempo,
+    swing: Swing,
+    click: &Click,
+) -> Result<()> {
+    let mut editor = Editor::new(Pattern::parse(path)?, path, tempo.value());
+    let mut player = Player::start(
+        editor.pattern.clone(),
+        instrumentation.clone(),
+        samples_path,
+        tempo,
+        swing,
+        click,
+    )?;
+
+    let mut stdout = io::stdout();
+    display::open(&mut stdout)?;
+
+    let result = loop {
+        if let Err(e) = editor.draw(&mut stdout) {
+            break Err(e.into());
+        }
+        let key = match event::read() {
+            Ok(Event::Key(key)) => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e.into()),
+        };
+        let command = match Command::from_key(key) {
+            Some(command) => command,
+            None => continue,
+        };
+
+        let outcome = match editor.apply(command) {
+            Effect::Nothing => Ok(()),
+            Effect::Remix => {
+                // the editor never slows down below 1 beat per minute
+                if let Some(tempo) = Tempo::new(editor.tempo) {
+                    player.set_tempo(tempo);
+                }
+                player.swap(editor.pattern.clone(), instrumentation.clone())
+            }
+            Effect::Audition(i, amplitude) => match instrumentation.get(&i) {
+                Some(binding) => audio::audition(binding, samples_path, amplitude),
+                None => {
+                    editor.status = Problem::UnboundInstrument(i).to_string();
+                    Ok(())
+                }
+            },
+            Effect::Quit => break Ok(()),
+        };
+        if let Err(e) = outcome {
+            editor.status = format!("error: {}", e);
+        }
+    };
+
+    display::close(&mut stdout)?;
+
+    result
+}
+
+/// Represents a command given from the keyboard.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Up,
+    Down,
+    Left,
+    Right,
+    Toggle,
+    Louder,
+    Softer,
+    Faster,
+    Slower,
+    Audition,
+    Save,
+    Quit,
+}
+
+impl Command {
+    /// Returns the command bound to the key given, if any.
+    fn from_key(key: KeyEvent) -> Option<Command> {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Some(Command::Quit);
+        }
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => Some(Command::Up),
+            KeyCode::Down | KeyCode::Char('j') => Some(Command::Down),
+            KeyCode::Left | KeyCode::Char('h') => Some(Command::Left),
+            KeyCode::Right | KeyCode::Char('l') => Some(Command::Right),
+            KeyCode::Char(' ') | KeyCode::Enter => Some(Command::Toggle),
+            KeyCode::Char('+') | KeyCode::Char('=') => Some(Command::Louder),
+            KeyCode::Char('-') => Some(Command::Softer),
+            KeyCode::Char('>') | KeyCode::Char('.') => Some(Command::Faster),
+            KeyCode::Char('<') | KeyCode::Char(',') => Some(Command::Slower),
+            KeyCode::Char('a') => Some(Command::Audition),
+            KeyCode::Char('s') => Some(Command::Save),
+            KeyCode::Char('q') | KeyCode::Esc => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Represents what has to happen after a command is applied.
+#[derive(Debug, PartialEq)]
+enum Effect {
+    Nothing,
+    Remix,
+    Audition(Instrument, Amplitude),
+    Quit,
+}
+
+/// Represents the state of the step sequencer editor.
+struct Editor {
+    pattern: Pattern,
+    path: PathBuf,
+    tempo: u16,
+    /// The track of the cursor.
+    row: usize,
+    /// The step of the cursor, counted across the pattern's measures.
+    column: usize,
+    /// Whether the pattern has changed since it was last saved.
+    modified: bool,
+    /// Whether quitting is waiting to be confirmed.
+    quitting: bool,
+    status: String,
+}
+
+impl Editor {
+    /// Returns an editor of the pattern given, saved to the path given, with
+    /// the cursor on the first step of the first track.
+    fn new(pattern: Pattern, path: &Path, tempo: u16) -> Editor {
+        Editor {
+            pattern,
+            path: path.into(),
+            tempo,
+            row: 0,
+            column: 0,
+            modified: false,
+            quitting: false,
+            status: String::new(),
+        }
+    }
+
+    /// Returns the instrument of the cursor's track, if the pattern has tracks.
+    fn instrument(&self) -> Option<Instrument> {
+        self.pattern.instruments().nth(self.row).cloned()
+    }
+
+    /// Applies a command to the pattern or the cursor.
+    fn apply(&mut self, command: Command) -> Effect {
+        let quitting = self.quitting;
+        self.quitting = false;
+        self.status.clear();
+
+        let rows = self.pattern.instruments().len();
+        let columns = self.pattern.steps() * self.pattern.measures();
+        let instrument = self.instrument();
+        let amplitude = instrument
+            .as_ref()
+            .and_then(|i| self.pattern.get(i))
+            .map_or(1.0, |(_, a)| a.value());
+
+        match (command, instrument) {
+            (Command::Up, _) => self.row = self.row.saturating_sub(1),
+            (Command::Down, _) => self.row = (self.row + 1).min(rows.saturating_sub(1)),
+            (Command::Left, _) => self.column = self.column.saturating_sub(1),
+            (Command::Right, _) => self.column = (self.column + 1).min(columns - 1),
+            (Command::Toggle, Some(i)) => return self.toggle(&i),
+            (Command::Louder, Some(i)) => return self.adjust(&i, amplitude + AMPLITUDE_STEP),
+            (Command::Softer, Some(i)) => return self.adjust(&i, amplitude - AMPLITUDE_STEP),
+            (Command::Faster, _) => {
+                self.tempo = self.tempo.saturating_add(TEMPO_STEP);
+                return Effect::Remix;
+            }
+            (Command::Slower, _) => {
+                self.tempo = self.tempo.saturating_sub(TEMPO_STEP).max(1);
+                return Effect::Remix;
+            }
+            (Command::Audition, Some(i)) => {
+                return Effect::Audition(
+                    i,
+                    Amplitude::new(amplitude).unwrap_or_else(Amplitude::max),
+                )
+            }
+            (Command::Save, _) => match self.pattern.save(&self.path) {
+                Ok(()) => {
+                    self.modified = false;
+                    self.status = format!("saved {}", self.path.display());
+                }
+                Err(e) => self.status = format!("error: {}", e),
+            },
+            (Command::Quit, _) => {
+                if !self.modified || quitting {
+                    return Effect::Quit;
+                }
+                self.quitting = true;
+                self.status = "unsaved changes, press q again to quit or s to save".into();
+            }
+            _ => {}
+        }
+
+        Effect::Nothing
+    }
+
+    /// Toggles the cursor's step of the track of the instrument given.
+    fn toggle(&mut self, i: &Instrument) -> Effect {
+        match self.pattern.toggle_step(i, self.column) {
+            Ok(_) => self.modify(),
+            Err(_) => Effect::Nothing,
+        }
+    }
+
+    /// Sets the amplitude of the track of the instrument given to the value
+    /// given, clamped to the range of [0,1].
+    fn adjust(&mut self, i: &Instrument, v: f32) -> Effect {
+        // round to tenths so the amplitude saves without drift
+        let v = (v.clamp(0.0, 1.0) * 10.0).round() / 10.0;
+        match Amplitude::new(v).map(|a| self.pattern.set_amplitude(i, a)) {
+            Some(Ok(())) => self.modify(),
+            _ => Effect::Nothing,
+        }
+    }
+
+    /// Marks the pattern as modified and returns the effect of a change to it.
+    fn modify(&mut self) -> Effect {
+        self.modified = true;
+        Effect::Remix
+    }
+
+    /// Draws the editor to the terminal given.
+    fn draw(&self, w: &mut impl Write) -> io::Result<()> {
+        queue!(w, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        queue!(
+            w,
+            Print(format!(
+                "{}{}  tempo {}",
+                self.path.display(),
+                if self.modified { " *" } else { "" },
+                self.tempo
+            ))
+        )?;
+
+        let line = display::grid(w, &self.pattern, 2, |r, c| {
+            (r, c) == (self.row, self.column)
+        })?;
+
+        queue!(
+            w,
+            cursor::MoveTo(0, line + 1),
+            Print(&self.status),
+            cursor::MoveTo(0, line + 2),
+            Print(HELP)
+        )?;
+
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Velocity;
+
+    fn editor() -> Editor {
+        let s = "snare |----|x---|----|x---|\n\
+                 kick  |x---|----|x---|----| 0.5\n";
+        let p = Path::new("test");
+        Editor::new(Pattern::read(s.as_bytes(), p).unwrap(), p, 120)
+    }
+
+    #[test]
+    fn test_command_from_key() {
+        let key = |c| KeyEvent::new(c, KeyModifiers::NONE);
+
+        assert_eq!(Command::from_key(key(KeyCode::Left)), Some(Command::Left));
+        assert_eq!(
+            Command::from_key(key(KeyCode::Char('j'))),
+            Some(Command::Down)
+        );
+        assert_eq!(
+            Command::from_key(key(KeyCode::Char(' '))),
+            Some(Command::Toggle)
+        );
+        assert_eq!(
+            Command::from_key(key(KeyCode::Char('>'))),
+            Some(Command::Faster)
+        );
+        assert_eq!(
+            Command::from_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(Command::Quit)
+        );
+        assert_eq!(Command::from_key(key(KeyCode::Char('c'))), None);
+        assert_eq!(Command::from_key(key(KeyCode::Char('z'))), None);
+    }
+
+    #[test]
+    fn test_editor_move() {
+        let mut e = editor();
+
+        assert_eq!(e.apply(Command::Up), Effect::Nothing);
+        assert_eq!((e.row, e.column), (0, 0));
+        (0..3).for_each(|_| {
+            e.apply(Command::Down);
+        });
+        (0..20).for_each(|_| {
+            e.apply(Command::Right);
+        });
+        assert_eq!((e.row, e.column), (1, 15));
+        assert_eq!(e.instrument(), Some(Instrument::from("kick")));
+    }
+
+    #[test]
+    fn test_editor_edit() {
+        let mut e = editor();
+        let snare = Instrument::from("snare");
+        let kick = Instrument::from("kick");
+
+        e.apply(Command::Right);
+        assert_eq!(e.apply(Command::Toggle), Effect::Remix);
+        assert!(e.modified);
+        assert_eq!(
+            e.pattern.get(&snare).unwrap().0.get(1),
+            Some(Some(Velocity::normal()))
+        );
+
+        e.apply(Command::Down);
+        assert_eq!(e.apply(Command::Louder), Effect::Remix);
+        assert_eq!(e.pattern.get(&kick).unwrap().1.value(), 0.6);
+        (0..10).for_each(|_| {
+            e.apply(Command::Louder);
+        });
+        assert_eq!(e.pattern.get(&kick).unwrap().1.value(), 1.0);
+
+        assert_eq!(e.apply(Command::Slower), Effect::Remix);
+        assert_eq!(e.tempo, 119);
+        assert!(matches!(e.apply(Command::Audition), Effect::Audition(i, _) if i == kick));
+    }
+
+    #[test]
+    fn test_editor_draw() {
+        let mut w = Vec::new();
+        editor().draw(&mut w).unwrap();
+        let screen = String::from_utf8(w).unwrap();
+
+        assert!(screen.contains("snare |"));
+        assert!(screen.contains("| 0.5"));
+        assert!(screen.contains(HELP));
+    }
+
+    #[test]
+    fn test_editor_quit() {
+        let mut e = editor();
+
+        assert_eq!(e.apply(Command::Quit), Effect::Quit);
+        e.apply(Command::Toggle);
+        assert_eq!(e.apply(Command::Quit), Effect::Nothing);
+        assert!(!e.status.is_empty());
+        assert_eq!(e.apply(Command::Quit), Effect::Quit);
+    }
+}
@@ -1,14 +1,16 @@
-use rodio;
 use std::{
     path::PathBuf,
     {io, result},
 };
 use thiserror::Error;
 
+use crate::diagnostic::Diagnostic;
+
 /// A type that represents a success or failure.
 pub type Result<T> = result::Result<T, Error>;
 
 /// An enum that captures all possible error conditions of this crate.
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
     /// An I/O error occurred.
@@ -16,27 +18,91 @@ pub enum Error {
     IOError(#[from] io::Error),
 
     /// An error occurred while parsing a structured file.
-    #[error("parse error {0}")]
-    ParseError(String),
+    #[error("{0}")]
+    ParseError(Diagnostic),
+
+    /// A swing amount is not a percentage in the range of [0,100).
+    #[error("invalid swing {0}, expected a percentage in [0,100)")]
+    InvalidSwingError(String),
+
+    /// A tempo is not a positive number of beats per minute.
+    #[error("invalid tempo {0}, expected beats per minute in [1,65535]")]
+    InvalidTempoError(String),
+
+    /// An amplitude is not a number in the range of [0,1].
+    #[error("invalid amplitude {0}, expected a number in [0,1]")]
+    InvalidAmplitudeError(String),
+
+    /// A length of playback is neither a positive number of seconds nor of bars.
+    #[error("invalid duration {0}, expected seconds (e.g. 30s) or bars (e.g. 8bars)")]
+    InvalidLengthError(String),
 
-    /// More than one step sequence is listed for the same instrument in a
-    /// pattern file.
-    #[error("duplicate pattern {0}")]
-    DuplicatePatternError(String),
+    /// A pattern or song is looped no times.
+    #[error("invalid loop count {0}, expected a positive number")]
+    InvalidLoopCountError(u32),
 
-    /// More than one audio file is bound to the same instrument in an
-    /// instrumentation file.
+    /// A parameter of a binding is outside of its range.
+    #[error("invalid parameter {0}")]
+    InvalidParameterError(String),
+
+    /// An instrument's name can't be written to a pattern file.
+    #[error("invalid instrument `{0}`, expected a name without whitespace or `#`")]
+    InvalidInstrumentError(String),
+
+    /// A pattern has no track for an instrument.
+    #[error("unknown instrument {0}")]
+    UnknownInstrumentError(String),
+
+    /// A pattern already has a track for an instrument.
     #[error("duplicate instrument {0}")]
     DuplicateInstrumentError(String),
 
+    /// A track's steps do not fit evenly into a pattern's measures of the number
+    /// of steps given.
+    #[error("{0} steps do not fit evenly into measures of {1} steps")]
+    StepCountError(usize, usize),
+
+    /// A pattern would have no steps per measure or no measures.
+    #[error("a pattern of {0} steps per measure over {1} measures is empty")]
+    EmptyPatternError(usize, usize),
+
+    /// A pattern of several measures has a number of steps per measure that
+    /// does not divide into its number of beats, so it cannot be written out.
+    #[error("{0} steps per measure do not divide into {1} beats across several measures")]
+    BeatCountError(usize, usize),
+
+    /// A step is beyond the end of a track.
+    #[error("step {0} out of range")]
+    StepOutOfRangeError(usize),
+
     /// A necessary file does not exist.
     #[error("file does not exist {0}")]
     FileDoesNotExistError(PathBuf),
 
+    /// Problems were found with an instrumentation in strict mode.
+    #[error("aborting due to {0} instrumentation problem(s)")]
+    ValidationError(usize),
+
     /// An error occurred while decoding an audio sample file.
     #[error("audio decoder error")]
     AudioDecoderError(#[from] rodio::decoder::DecoderError),
 
+    /// An error occurred while encoding an audio file.
+    #[error("audio encoder error")]
+    AudioEncoderError(#[from] hound::Error),
+
+    /// An audio file cannot be rendered with the bit depth requested.
+    #[error("unsupported bit depth {0}")]
+    UnsupportedBitDepthError(u16),
+
+    /// An audio file cannot be rendered at the sample rate requested.
+    #[error("unsupported sample rate {0}")]
+    UnsupportedSampleRateError(u32),
+
+    /// A render is neither looped a number of times nor cut short.
+    #[error("cannot render endlessly, expected a number of loops or a duration")]
+    UnboundedRenderError(),
+
     /// An error occurred accessing the default audio device.
     #[error("audio device error")]
     AudioDeviceError(),
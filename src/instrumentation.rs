@@ -1,47 +1,109 @@
 extern crate nom;
 
+use indexmap::{map::IntoIter, IndexMap, IndexSet};
 use nom::{
+    branch::alt,
     bytes::complete::is_not,
-    character::complete::{space0, space1},
-    IResult,
+    character::complete::{char, space0, space1},
+    combinator::{map, verify},
+    multi::separated_list1,
+    sequence::{preceded, separated_pair},
 };
+use rodio::Source;
 use std::{
-    collections::hash_map::IntoIter,
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     fmt,
     fs::File,
+    hash::{Hash, Hasher},
     io::{BufRead, BufReader},
+    ops::RangeInclusive,
     path::{Path, PathBuf},
 };
 
 use crate::{
+    comment,
+    diagnostic::{fail, parse_end, span_of, Diagnostic, ParseResult},
     error::{Error::*, Result},
-    pattern::Instrument,
+    pattern::{Instrument, Pattern, Velocity},
 };
 
+/// Separates the key and the value of a binding's parameter.
+const PARAMETER: char = '=';
+
+/// The key of a binding's stereo position.
+const PAN: &str = "pan";
+
+/// Separates the sample files of a binding.
+const LAYER: char = ',';
+
+/// Separates a sample file of a binding from the velocity it is played from.
+const VELOCITY: char = '@';
+
+/// The key and the values of how a binding picks among its sample files.
+const SELECT: &str = "select";
+const SELECT_CYCLE: &str = "cycle";
+const SELECT_RANDOM: &str = "random";
+
+/// The key of a binding's choke group.
+const CHOKE: &str = "choke";
+
+/// The key and the range of a binding's gain in decibels.
+const GAIN: &str = "gain";
+const GAIN_RANGE: RangeInclusive<f32> = -60.0..=12.0;
+
+/// The key and the range of a binding's pitch shift in semitones.
+const PITCH: &str = "pitch";
+const PITCH_RANGE: RangeInclusive<f32> = -24.0..=24.0;
+
+/// The key and the range of a binding's fine tuning in cents.
+const TUNE: &str = "tune";
+const TUNE_RANGE: RangeInclusive<f32> = -100.0..=100.0;
+
+/// The keys of a binding's trim offsets in milliseconds.
+const START: &str = "start";
+const END: &str = "end";
+
+/// The keys of a binding's fades in milliseconds.
+const ATTACK: &str = "attack";
+const DECAY: &str = "decay";
+const RELEASE: &str = "release";
+
+/// The range of a binding's times in milliseconds.
+const TIME_RANGE: RangeInclusive<f32> = 0.0..=60_000.0;
+
+/// The flag that reverses a binding's sample file.
+const REVERSE: &str = "reverse";
+
 /// Represents the contents of an instrumentation file.
 ///
 /// An instrumentation file binds the instruments from a pattern file to audio
 /// sample files. Each line of an instrumentation file contains an instrument name
-/// and an audio file name. Each instrument may only appear once, but a single
-/// audio file may be bound to multiple instruments.
+/// and an audio file name, optionally followed by parameters of the binding
+/// (e.g. `pan=-0.5`, `choke=hi-hat` or `pitch=7`). An instrument may also be
+/// bound to a comma-separated set of audio files, each played from the
+/// velocity following its `@` (e.g. `sd-soft.wav,sd-hard.wav@0.7`). Each
+/// instrument may only appear once, but a single audio file may be bound to
+/// multiple instruments. The order of the bindings is kept.
+/// Blank lines are ignored, and comments start with `#` and run to the end of
+/// the line.
 ///
 /// # Example
 ///
 /// This is an example of an instrumentation file's contents that binds five
-/// instruments to four audio sample files.
+/// instruments to four audio sample files, with the toms panned apart and the
+/// second tom tuned a fourth higher.
 ///
 /// > Note that `tom.wav` is used for both `tom-1` and `tom-2`.
 ///
 /// ```text
 /// hi-hat hh.wav
-/// tom-1  tom.wav
-/// tom-2  tom.wav
+/// tom-1  tom.wav pan=-0.5
+/// tom-2  tom.wav pan=0.5 pitch=5
 /// snare  snare.wav
 /// kick   kick.wav
 /// ```
-#[derive(Debug)]
-pub struct Instrumentation(HashMap<SampleFile, HashSet<Instrument>>);
+#[derive(Clone, Debug)]
+pub struct Instrumentation(IndexMap<Instrument, Binding>);
 
 impl Instrumentation {
     /// Parses an instrumentation file located at the path given.
@@ -50,51 +112,644 @@ impl Instrumentation {
             return Err(FileDoesNotExistError(p.into()));
         }
         let f = File::open(p)?;
-        let r = BufReader::new(f);
-        let mut m: HashMap<SampleFile, HashSet<Instrument>> = HashMap::new();
-        for l in r.lines() {
-            let l = l?;
-            match parse_binding(&l[..]) {
-                Ok((_, (i, s))) => {
-                    if m.values().any(|is| is.contains(&i)) {
-                        return Err(DuplicateInstrumentError(i.to_string()));
-                    } else if let Some(is) = m.get_mut(&s) {
-                        is.insert(i);
-                    } else {
-                        let mut is = HashSet::new();
-                        is.insert(i);
-                        m.insert(s, is);
+
+        Instrumentation::read(BufReader::new(f), p)
+    }
+
+    /// Reads the contents of an instrumentation file from the reader given.
+    /// Parse errors are reported against the path given.
+    pub fn read<R: BufRead>(r: R, p: &Path) -> Result<Instrumentation> {
+        let mut m: IndexMap<Instrument, Binding> = IndexMap::new();
+        for (n, l) in r.lines().enumerate() {
+            let (n, l) = (n + 1, l?);
+            if let Some(w) = comment::within_first_word(&l) {
+                let reason = format!("`{}` in instrument `{}`", comment::COMMENT, w);
+                return Err(ParseError(Diagnostic::new(
+                    p,
+                    n,
+                    &l,
+                    span_of(&l, w),
+                    reason,
+                )));
+            }
+            let (content, _) = comment::split(&l[..]);
+            if content.trim().is_empty() {
+                continue;
+            }
+            match parse_binding(content) {
+                Ok((_, (i, b))) => {
+                    if m.contains_key(&i) {
+                        let span = l.split_whitespace().next().map(|i| span_of(&l, i));
+                        let reason = format!("duplicate instrument `{}`", i);
+                        return Err(ParseError(Diagnostic::new(
+                            p,
+                            n,
+                            &l,
+                            span.unwrap_or_default(),
+                            reason,
+                        )));
                     }
+                    m.insert(i, b);
+                }
+                Err(e) => {
+                    return Err(ParseError(Diagnostic::from_failure(
+                        p,
+                        n,
+                        &l,
+                        e,
+                        "invalid binding",
+                    )))
                 }
-                _ => return Err(ParseError(l)),
             }
         }
 
         Ok(Instrumentation(m))
     }
 
-    /// Returns an owning iterator over the instrumentation bindings.
-    pub fn into_iter(self) -> IntoIter<SampleFile, HashSet<Instrument>> {
-        self.0.into_iter()
+    /// Returns the binding of the instrument given.
+    pub fn get(&self, i: &Instrument) -> Option<&Binding> {
+        self.0.get(i)
+    }
+
+    /// Returns the sample files and the instruments bound to each, in the order
+    /// they were first bound.
+    pub fn samples(&self) -> IndexMap<&SampleFile, IndexSet<&Instrument>> {
+        let mut m: IndexMap<&SampleFile, IndexSet<&Instrument>> = IndexMap::new();
+        for (i, b) in self.0.iter() {
+            for l in b.layers.iter() {
+                m.entry(&l.sample_file).or_default().insert(i);
+            }
+        }
+
+        m
+    }
+
+    /// Returns the distinct bindings and the instruments bound by each, in the
+    /// order they were first bound. Instruments bound to the same sample files
+    /// with the same parameters share a binding.
+    pub fn bindings(&self) -> IndexMap<&Binding, IndexSet<&Instrument>> {
+        let mut m: IndexMap<&Binding, IndexSet<&Instrument>> = IndexMap::new();
+        for (i, b) in self.0.iter() {
+            m.entry(b).or_default().insert(i);
+        }
+
+        m
+    }
+
+    /// Removes the sample files that do not exist in the samples directory
+    /// given from their bindings, along with the bindings left without any, so
+    /// that the instruments bound to them are silent.
+    pub fn remove_missing(&mut self, samples_path: &Path) {
+        for b in self.0.values_mut() {
+            b.layers.retain(|l| {
+                !matches!(
+                    l.sample_file.with_parent(samples_path),
+                    Err(FileDoesNotExistError(_))
+                )
+            });
+        }
+        self.0.retain(|_, b| !b.layers.is_empty());
+    }
+
+    /// Checks the instrumentation against the pattern it binds and the samples
+    /// directory given. Returns the problems found, unbound instruments first,
+    /// in the order they were declared.
+    pub fn validate(&self, pattern: &Pattern, samples_path: &Path) -> Vec<Problem> {
+        let unbound = pattern
+            .instruments()
+            .filter(|i| !self.0.contains_key(*i))
+            .map(|i| Problem::UnboundInstrument(i.clone()));
+
+        let missing: IndexSet<PathBuf> = self
+            .0
+            .values()
+            .flat_map(|b| b.layers.iter())
+            .filter_map(|l| match l.sample_file.with_parent(samples_path) {
+                Err(FileDoesNotExistError(p)) => Some(p),
+                _ => None,
+            })
+            .collect();
+
+        // only the sample files played from an offset need decoding
+        let mut lengths: HashMap<PathBuf, Option<f32>> = HashMap::new();
+        let mut past_end: IndexSet<(PathBuf, u32)> = IndexSet::new();
+        for b in self.0.values().filter(|b| b.start > 0.0) {
+            for l in b.layers.iter() {
+                if let Ok(p) = l.sample_file.with_parent(samples_path) {
+                    let length = *lengths.entry(p.0.clone()).or_insert_with(|| length(&p.0));
+                    if length.is_some_and(|ms| b.start >= ms) {
+                        past_end.insert((p.0, b.start.to_bits()));
+                    }
+                }
+            }
+        }
+
+        unbound
+            .chain(missing.into_iter().map(Problem::MissingSampleFile))
+            .chain(
+                past_end
+                    .into_iter()
+                    .map(|(p, start)| Problem::StartPastEnd(p, f32::from_bits(start))),
+            )
+            .collect()
+    }
+}
+
+impl IntoIterator for Instrumentation {
+    type Item = (SampleFile, IndexSet<Instrument>);
+    type IntoIter = IntoIter<SampleFile, IndexSet<Instrument>>;
+
+    /// Returns an owning iterator over the sample files and the instruments
+    /// bound to each, in the order they were first bound.
+    fn into_iter(self) -> Self::IntoIter {
+        let mut m: IndexMap<SampleFile, IndexSet<Instrument>> = IndexMap::new();
+        for (i, b) in self.0 {
+            for l in b.layers {
+                m.entry(l.sample_file).or_default().insert(i.clone());
+            }
+        }
+
+        m.into_iter()
     }
 }
 
 impl fmt::Display for Instrumentation {
+    /// Writes the bindings in the instrumentation file format with the sample
+    /// files lined up. Parameters are written only when they differ from
+    /// their defaults.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .0
+            .keys()
+            .map(|i| i.to_string().chars().count())
+            .max()
+            .unwrap_or(0);
+        for (i, b) in self.0.iter() {
+            writeln!(f, "{:width$} {}", i.to_string(), b, width = width)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents a mismatch between an instrumentation, the pattern it binds and
+/// the samples directory.
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// A pattern's instrument is not bound to a sample file, so its track is silent.
+    UnboundInstrument(Instrument),
+
+    /// A sample file does not exist in the samples directory.
+    MissingSampleFile(PathBuf),
+
+    /// A sample file ends before the offset in milliseconds it is played from,
+    /// so the binding plays nothing.
+    StartPastEnd(PathBuf, f32),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::UnboundInstrument(i) => {
+                write!(f, "instrument `{}` is not bound to a sample file", i)
+            }
+            Problem::MissingSampleFile(p) => {
+                write!(f, "sample file {} does not exist", p.display())
+            }
+            Problem::StartPastEnd(p, start) => write!(
+                f,
+                "sample file {} ends before its start at {} ms",
+                p.display(),
+                start
+            ),
+        }
+    }
+}
+
+/// Represents how an instrument is played: the sample files it is bound to and
+/// the parameters of the binding.
+#[derive(Clone, Debug)]
+pub struct Binding {
+    layers: Vec<Layer>,
+    select: Select,
+    pan: Pan,
+    choke: Option<String>,
+    gain: f32,
+    pitch: f32,
+    tune: f32,
+    start: f32,
+    end: Option<f32>,
+    reverse: bool,
+    attack: f32,
+    decay: f32,
+    release: f32,
+}
+
+impl Binding {
+    /// Returns a binding to the sample file given with default parameters.
+    pub fn new(sample_file: SampleFile) -> Binding {
+        Binding::with_layers(vec![Layer {
+            sample_file,
+            velocity: 0.0,
+        }])
+    }
+
+    /// Returns a binding to the sample files given with default parameters.
+    fn with_layers(layers: Vec<Layer>) -> Binding {
+        Binding {
+            layers,
+            select: Select::default(),
+            pan: Pan::default(),
+            choke: None,
+            gain: 0.0,
+            pitch: 0.0,
+            tune: 0.0,
+            start: 0.0,
+            end: None,
+            reverse: false,
+            attack: 0.0,
+            decay: 0.0,
+            release: 0.0,
+        }
+    }
+
+    /// Returns the sample files of the binding with their velocities.
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Returns how the binding picks among sample files of the same velocity.
+    pub fn select(&self) -> Select {
+        self.select
+    }
+
+    /// Returns the velocity layers of the binding from the softest, each as
+    /// the smallest velocity of the steps it plays and the indices of its
+    /// sample files.
+    pub fn velocity_layers(&self) -> Vec<(f32, Vec<usize>)> {
+        let mut v: Vec<(f32, Vec<usize>)> = Vec::new();
+        for (n, l) in self.layers.iter().enumerate() {
+            match v.iter_mut().find(|(velocity, _)| *velocity == l.velocity) {
+                Some((_, indices)) => indices.push(n),
+                None => v.push((l.velocity, vec![n])),
+            }
+        }
+        v.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        v
+    }
+
+    /// Returns the stereo position of the binding.
+    pub fn pan(&self) -> Pan {
+        self.pan
+    }
+
+    /// Sets the stereo position of the binding.
+    pub fn set_pan(&mut self, pan: Pan) {
+        self.pan = pan;
+    }
+
+    /// Returns the name of the choke group of the binding, if any.
+    pub fn choke(&self) -> Option<&str> {
+        self.choke.as_deref()
+    }
+
+    /// Puts the binding in the choke group of the name given. Triggering any
+    /// binding of a choke group cuts off the sounding hits of the group.
+    pub fn set_choke(&mut self, group: &str) {
+        self.choke = Some(group.into());
+    }
+
+    /// Returns the gain of the binding in decibels.
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    /// Sets the gain of the binding in decibels, which must lie in the range
+    /// of [-60,12].
+    pub fn set_gain(&mut self, db: f32) -> Result<()> {
+        self.gain = check_number(GAIN, db, GAIN_RANGE)?;
+
+        Ok(())
+    }
+
+    /// Returns the pitch shift of the binding in semitones.
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    /// Sets the pitch shift of the binding in semitones, which must lie in the
+    /// range of [-24,24].
+    pub fn set_pitch(&mut self, semitones: f32) -> Result<()> {
+        self.pitch = check_number(PITCH, semitones, PITCH_RANGE)?;
+
+        Ok(())
+    }
+
+    /// Returns the fine tuning of the binding in cents.
+    pub fn tune(&self) -> f32 {
+        self.tune
+    }
+
+    /// Sets the fine tuning of the binding in cents, which must lie in the
+    /// range of [-100,100].
+    pub fn set_tune(&mut self, cents: f32) -> Result<()> {
+        self.tune = check_number(TUNE, cents, TUNE_RANGE)?;
+
+        Ok(())
+    }
+
+    /// Returns the rate at which the sample file is played back for the pitch
+    /// shift and fine tuning of the binding, where 1 is the original pitch.
+    pub fn rate(&self) -> f32 {
+        ((self.pitch + self.tune / 100.0) / 12.0).exp2()
+    }
+
+    /// Returns the offset in milliseconds from which the sample file is
+    /// played.
+    pub fn start(&self) -> f32 {
+        self.start
+    }
+
+    /// Sets the offset in milliseconds from which the sample file is played,
+    /// which must lie in the range of [0,60000] and before the end, if any.
+    pub fn set_start(&mut self, ms: f32) -> Result<()> {
+        let ms = check_number(START, ms, TIME_RANGE)?;
+        if let Some(end) = self.end.filter(|end| *end <= ms) {
+            let reason = format!("{}={}, expected less than {}={}", START, ms, END, end);
+            return Err(InvalidParameterError(reason));
+        }
+        self.start = ms;
+
+        Ok(())
+    }
+
+    /// Returns the offset in milliseconds at which the sample file is cut
+    /// short, if any.
+    pub fn end(&self) -> Option<f32> {
+        self.end
+    }
+
+    /// Sets or clears the offset in milliseconds at which the sample file is
+    /// cut short, which must lie in the range of [0,60000] and after the start.
+    pub fn set_end(&mut self, ms: Option<f32>) -> Result<()> {
+        let ms = ms.map(|ms| check_number(END, ms, TIME_RANGE)).transpose()?;
+        if let Some(ms) = ms.filter(|ms| *ms <= self.start) {
+            let reason = format!(
+                "{}={}, expected more than {}={}",
+                END, ms, START, self.start
+            );
+            return Err(InvalidParameterError(reason));
+        }
+        self.end = ms;
+
+        Ok(())
+    }
+
+    /// Returns whether the sample file is played backwards.
+    pub fn reverse(&self) -> bool {
+        self.reverse
+    }
+
+    /// Sets whether the sample file is played backwards.
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    /// Returns the time in milliseconds over which the sample file fades in.
+    pub fn attack(&self) -> f32 {
+        self.attack
+    }
+
+    /// Sets the time in milliseconds over which the sample file fades in,
+    /// which must lie in the range of [0,60000].
+    pub fn set_attack(&mut self, ms: f32) -> Result<()> {
+        self.attack = check_number(ATTACK, ms, TIME_RANGE)?;
+
+        Ok(())
+    }
+
+    /// Returns the time in milliseconds over which the sample file decays by
+    /// 60 dB after its attack, or 0 if it does not decay.
+    pub fn decay(&self) -> f32 {
+        self.decay
+    }
+
+    /// Sets the time in milliseconds over which the sample file decays by
+    /// 60 dB after its attack, or 0 for no decay, which must lie in the range
+    /// of [0,60000].
+    pub fn set_decay(&mut self, ms: f32) -> Result<()> {
+        self.decay = check_number(DECAY, ms, TIME_RANGE)?;
+
+        Ok(())
+    }
+
+    /// Returns the time in milliseconds over which the sample file fades out
+    /// before its end.
+    pub fn release(&self) -> f32 {
+        self.release
+    }
+
+    /// Sets the time in milliseconds over which the sample file fades out
+    /// before its end, which must lie in the range of [0,60000].
+    pub fn set_release(&mut self, ms: f32) -> Result<()> {
+        self.release = check_number(RELEASE, ms, TIME_RANGE)?;
+
+        Ok(())
+    }
+
+    /// Returns the fields of the binding that identify it, with the numbers as
+    /// their bits.
+    ///
+    /// Comparing bits is sound because every number is checked against a range
+    /// when it is set, so none is NaN and equality is total, and -0 is
+    /// normalised to 0, so equal values hash alike.
+    fn key(&self) -> BindingKey<'_> {
+        (
+            self.layers
+                .iter()
+                .map(|l| (&l.sample_file, l.velocity.to_bits()))
+                .collect(),
+            self.select,
+            self.pan,
+            self.choke(),
+            [
+                self.gain,
+                self.pitch,
+                self.tune,
+                self.start,
+                self.attack,
+                self.decay,
+                self.release,
+            ]
+            .map(f32::to_bits),
+            self.end.map(f32::to_bits),
+            self.reverse,
+        )
+    }
+}
+
+impl PartialEq for Binding {
+    fn eq(&self, other: &Binding) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Binding {}
+
+impl Hash for Binding {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl fmt::Display for Binding {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (k, vs) in self.0.iter() {
-            write!(f, "{} ", k)?;
-            for v in vs.iter() {
-                write!(f, "{} ", v)?;
+        for (n, l) in self.layers.iter().enumerate() {
+            if n > 0 {
+                write!(f, "{}", LAYER)?;
+            }
+            write!(f, "{}", l)?;
+        }
+        if self.select != Select::default() {
+            write!(f, " {}={}", SELECT, self.select)?;
+        }
+        if self.pan != Pan::default() {
+            write!(f, " {}={}", PAN, self.pan)?;
+        }
+        if let Some(group) = &self.choke {
+            write!(f, " {}={}", CHOKE, group)?;
+        }
+        for (key, v) in [(GAIN, self.gain), (PITCH, self.pitch), (TUNE, self.tune)] {
+            if v != 0.0 {
+                write!(f, " {}={}", key, v)?;
+            }
+        }
+        if self.start != 0.0 {
+            write!(f, " {}={}", START, self.start)?;
+        }
+        if let Some(end) = self.end {
+            write!(f, " {}={}", END, end)?;
+        }
+        if self.reverse {
+            write!(f, " {}", REVERSE)?;
+        }
+        for (key, v) in [
+            (ATTACK, self.attack),
+            (DECAY, self.decay),
+            (RELEASE, self.release),
+        ] {
+            if v != 0.0 {
+                write!(f, " {}={}", key, v)?;
             }
-            writeln!(f, "")?;
         }
 
         Ok(())
     }
 }
 
+/// A type that represents the fields of a binding that identify it.
+type BindingKey<'a> = (
+    Vec<(&'a SampleFile, u32)>,
+    Select,
+    Pan,
+    Option<&'a str>,
+    [u32; 7],
+    Option<u32>,
+    bool,
+);
+
+/// Represents one of the sample files of a binding and the smallest velocity
+/// of the steps it plays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    sample_file: SampleFile,
+    velocity: f32,
+}
+
+impl Layer {
+    /// Returns the sample file of the layer.
+    pub fn sample_file(&self) -> &SampleFile {
+        &self.sample_file
+    }
+
+    /// Returns the smallest velocity of the steps the layer plays.
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.sample_file)?;
+        if self.velocity != 0.0 {
+            write!(f, "{}{}", VELOCITY, self.velocity)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents how a binding picks among sample files of the same velocity.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Select {
+    /// The sample files take turns in the order they are bound.
+    #[default]
+    Cycle,
+
+    /// The sample files are picked at random, never twice in a row.
+    Random,
+}
+
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Select::Cycle => write!(f, "{}", SELECT_CYCLE),
+            Select::Random => write!(f, "{}", SELECT_RANDOM),
+        }
+    }
+}
+
+/// Represents a stereo position in the range of [-1,1] inclusive, from hard
+/// left to hard right. The default is the centre.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pan(f32);
+
+impl Pan {
+    /// Returns a stereo position of the value given, or `None` if the value is
+    /// outside of the range of [-1,1].
+    pub fn new(v: f32) -> Option<Pan> {
+        if (-1.0..=1.0).contains(&v) {
+            // normalise -0 so that equal positions hash alike
+            Some(Pan(v + 0.0))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the stereo position's value.
+    pub fn value(&self) -> f32 {
+        self.0
+    }
+}
+
+// positions are never NaN, so equality is total
+impl Eq for Pan {}
+
+impl Hash for Pan {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl fmt::Display for Pan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// Represents the location of an audio sample file.
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct SampleFile(pub PathBuf);
 
 impl SampleFile {
@@ -129,36 +784,312 @@ impl fmt::Display for SampleFile {
     }
 }
 
-/// A type that represents a binding in an instrumentation file.
-type Binding = (Instrument, SampleFile);
-
 /// Parses a binding from a single line of an instrumentation file.
-fn parse_binding(s: &str) -> IResult<&str, Binding> {
+fn parse_binding(s: &str) -> ParseResult<'_, (Instrument, Binding)> {
     let (s, _) = space0(s)?;
     let (s, instrument) = parse_instrument(s)?;
-    let (s, _) = space1(s)?;
+    let s = match space1(s).and_then(|(s, _)| parse_sound_file(s).map(|_| s)) {
+        Ok(s) => s,
+        Err(_) => return fail(s.trim_end(), "missing sample file"),
+    };
+    let (mut s, layers) = separated_list1(char(LAYER), parse_layer)(s)?;
+
+    let mut binding = Binding::with_layers(layers);
+    let mut keys: Vec<&str> = Vec::new();
+    loop {
+        match preceded(space1, alt((parse_parameter, map(parse_flag, |k| (k, "")))))(s) {
+            Ok((rest, (key, value))) => {
+                if keys.contains(&key) {
+                    return fail(key, format!("duplicate parameter `{}`", key));
+                }
+                set_parameter(&mut binding, key, value)?;
+                keys.push(key);
+                s = rest;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    let item = if keys.is_empty() {
+        "sample file"
+    } else {
+        "parameters"
+    };
+    let (s, _) = parse_end(s, item)?;
+
+    Ok((s, (Instrument::from(instrument), binding)))
+}
+
+/// Parses a sample file of a binding and the velocity it is played from, if
+/// any.
+fn parse_layer(s: &str) -> ParseResult<'_, Layer> {
     let (s, sound_file) = parse_sound_file(s)?;
+    let (s, velocity) = match preceded(char(VELOCITY), is_not(" \t,"))(s) {
+        Ok((s, v)) => (
+            s,
+            parse_number("velocity", v, 0.0..=Velocity::accent().value())?.1,
+        ),
+        Err(nom::Err::Error(_)) => (s, 0.0),
+        Err(e) => return Err(e),
+    };
 
     Ok((
         s,
-        (Instrument::from(instrument), SampleFile::from(sound_file)),
+        Layer {
+            sample_file: SampleFile::from(sound_file),
+            velocity,
+        },
     ))
 }
 
+/// Parses a `key=value` parameter of a binding.
+fn parse_parameter(s: &str) -> ParseResult<'_, (&str, &str)> {
+    separated_pair(is_not(" \t="), char(PARAMETER), is_not(" \t"))(s)
+}
+
+/// Parses a flag parameter of a binding, which has no value.
+fn parse_flag(s: &str) -> ParseResult<'_, &str> {
+    verify(is_not(" \t"), |k: &str| k == REVERSE)(s)
+}
+
+/// Sets the parameter of the key given to the value given on a binding.
+fn set_parameter<'a>(binding: &mut Binding, key: &'a str, value: &'a str) -> ParseResult<'a, ()> {
+    match key {
+        PAN => {
+            let (_, v) = parse_number(key, value, -1.0..=1.0)?;
+            binding.set_pan(Pan(v));
+        }
+        CHOKE => binding.set_choke(value),
+        SELECT => match value {
+            SELECT_CYCLE => binding.select = Select::Cycle,
+            SELECT_RANDOM => binding.select = Select::Random,
+            _ => return fail(value, format!("invalid {} `{}`", key, value)),
+        },
+        GAIN => binding.gain = parse_number(key, value, GAIN_RANGE)?.1,
+        PITCH => binding.pitch = parse_number(key, value, PITCH_RANGE)?.1,
+        TUNE => binding.tune = parse_number(key, value, TUNE_RANGE)?.1,
+        START => {
+            let (_, v) = parse_number(key, value, TIME_RANGE)?;
+            match binding.end {
+                Some(end) if end <= v => {
+                    return fail(
+                        value,
+                        format!("{} {} not before {} {}", key, value, END, end),
+                    )
+                }
+                _ => binding.start = v,
+            }
+        }
+        END => {
+            let (_, v) = parse_number(key, value, TIME_RANGE)?;
+            if v <= binding.start {
+                let reason = format!("{} {} not after {} {}", key, value, START, binding.start);
+                return fail(value, reason);
+            }
+            binding.end = Some(v);
+        }
+        ATTACK => binding.attack = parse_number(key, value, TIME_RANGE)?.1,
+        DECAY => binding.decay = parse_number(key, value, TIME_RANGE)?.1,
+        RELEASE => binding.release = parse_number(key, value, TIME_RANGE)?.1,
+        REVERSE if value.is_empty() => binding.reverse = true,
+        REVERSE => {
+            return fail(
+                value,
+                format!("unexpected value `{}` of flag `{}`", value, key),
+            )
+        }
+        _ => return fail(key, format!("unknown parameter `{}`", key)),
+    }
+
+    Ok((value, ()))
+}
+
+/// Parses the value of the numeric parameter of the key given, which must lie
+/// within the range given.
+fn parse_number<'a>(key: &str, value: &'a str, range: RangeInclusive<f32>) -> ParseResult<'a, f32> {
+    match value.parse::<f32>() {
+        Ok(v) if range.contains(&v) => Ok((value, v + 0.0)),
+        Ok(_) => fail(
+            value,
+            format!(
+                "{} {} outside [{},{}]",
+                key,
+                value,
+                range.start(),
+                range.end()
+            ),
+        ),
+        Err(_) => fail(value, format!("invalid {} `{}`", key, value)),
+    }
+}
+
+/// Returns the value of the numeric parameter of the key given, or an error if
+/// it lies outside the range given.
+fn check_number(key: &str, v: f32, range: RangeInclusive<f32>) -> Result<f32> {
+    if range.contains(&v) {
+        Ok(v + 0.0)
+    } else {
+        Err(InvalidParameterError(format!(
+            "{}={}, expected a number in [{},{}]",
+            key,
+            v,
+            range.start(),
+            range.end()
+        )))
+    }
+}
+
+/// Returns the length in milliseconds of the audio file located at the path
+/// given, or `None` if it can't be decoded.
+fn length(p: &Path) -> Option<f32> {
+    let decoder = rodio::Decoder::new(BufReader::new(File::open(p).ok()?)).ok()?;
+    let rate = decoder.channels() as f32 * decoder.sample_rate() as f32;
+
+    Some(decoder.count() as f32 / rate * 1_000.0)
+}
+
 /// Parses the instrument from a binding line.
-fn parse_instrument(s: &str) -> IResult<&str, &str> {
+fn parse_instrument(s: &str) -> ParseResult<'_, &str> {
     is_not(" \t")(s)
 }
 
 /// Parses the sound file from a binding line.
-fn parse_sound_file(s: &str) -> IResult<&str, &str> {
-    is_not(" \t\r\n")(s)
+fn parse_sound_file(s: &str) -> ParseResult<'_, &str> {
+    is_not(" \t\r\n,@")(s)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_read() {
+        let s = "# linndrum\n\
+                 \n\
+                 tom-1 tom.wav # high\n\
+                 tom-2 tom.wav\n\
+                 tom-3 tom.wav pan=0.5\n\
+                 \n";
+        let i = Instrumentation::read(s.as_bytes(), Path::new("test")).unwrap();
+        assert_eq!(
+            i.to_string(),
+            "tom-1 tom.wav\ntom-2 tom.wav\ntom-3 tom.wav pan=0.5\n"
+        );
+        assert_eq!(i.bindings().len(), 2);
+
+        let bindings: Vec<_> = i.into_iter().collect();
+
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].0, SampleFile::from("tom.wav"));
+        assert_eq!(bindings[0].1.len(), 3);
+    }
+
+    #[test]
+    fn test_validate() {
+        let s = "hihat chh.wav\n\
+                 snare sd.wav\n\
+                 kick  kick.wav\n\
+                 clap  clapping.wav\n";
+        let i = Instrumentation::read(s.as_bytes(), Path::new("test")).unwrap();
+        let p = Pattern::parse(Path::new("assets/patterns/standard")).unwrap();
+        let samples = Path::new("assets/samples/linndrum");
+
+        assert_eq!(
+            i.validate(&p, samples),
+            vec![
+                Problem::UnboundInstrument(Instrument::from("hi-hat")),
+                Problem::MissingSampleFile(samples.join("clapping.wav")),
+            ]
+        );
+
+        let s = "snare sd.wav,clapping.wav@1\n\
+                 clap  clapping.wav\n";
+        let mut i = Instrumentation::read(s.as_bytes(), Path::new("test")).unwrap();
+        i.remove_missing(samples);
+        let snare = i.get(&Instrument::from("snare")).unwrap();
+        assert_eq!(snare.layers().len(), 1);
+        assert_eq!(snare.layers()[0].sample_file(), &SampleFile::from("sd.wav"));
+        assert!(i.get(&Instrument::from("clap")).is_none());
+
+        // kick.wav is about 203 ms long
+        let s = "kick  kick.wav start=300\n\
+                 snare sd.wav start=50\n";
+        let i = Instrumentation::read(s.as_bytes(), Path::new("test")).unwrap();
+        let p = Pattern::read("kick |x---|\nsnare |--x-|".as_bytes(), Path::new("test")).unwrap();
+        assert_eq!(
+            i.validate(&p, samples),
+            vec![Problem::StartPastEnd(samples.join("kick.wav"), 300.0)]
+        );
+    }
+
+    #[test]
+    fn test_read_diagnostics() {
+        let cases = [
+            ("a a.wav\nb\n", 2, 2, "missing sample file"),
+            (
+                "a a.wav b.wav",
+                1,
+                9,
+                "unexpected `b.wav` after sample file",
+            ),
+            ("a a.wav\n\n  a b.wav", 3, 3, "duplicate instrument `a`"),
+            ("  hi#hat hh.wav", 1, 3, "`#` in instrument `hi#hat`"),
+            ("a a.wav pan=2", 1, 13, "pan 2 outside [-1,1]"),
+            ("a a.wav pan=left", 1, 13, "invalid pan `left`"),
+            ("a a.wav pitch=25", 1, 15, "pitch 25 outside [-24,24]"),
+            ("a a.wav tune=x", 1, 14, "invalid tune `x`"),
+            ("a a.wav,b.wav@2", 1, 15, "velocity 2 outside [0,1.25]"),
+            (
+                "a a.wav, b.wav",
+                1,
+                8,
+                "unexpected `, b.wav` after sample file",
+            ),
+            ("a a.wav select=all", 1, 16, "invalid select `all`"),
+            ("a a.wav start=-1", 1, 15, "start -1 outside [0,60000]"),
+            (
+                "a a.wav start=200 end=100",
+                1,
+                23,
+                "end 100 not after start 200",
+            ),
+            (
+                "a a.wav end=100 start=200",
+                1,
+                23,
+                "start 200 not before end 100",
+            ),
+            ("a a.wav end=0", 1, 13, "end 0 not after start 0"),
+            (
+                "a a.wav reverse=1",
+                1,
+                17,
+                "unexpected value `1` of flag `reverse`",
+            ),
+            (
+                "a a.wav reverse reverse",
+                1,
+                17,
+                "duplicate parameter `reverse`",
+            ),
+            ("a a.wav pan=1 pan=1", 1, 15, "duplicate parameter `pan`"),
+            ("a a.wav volume=1", 1, 9, "unknown parameter `volume`"),
+            ("a a.wav pan=1 b", 1, 15, "unexpected `b` after parameters"),
+        ];
+
+        for (s, line, column, reason) in cases.iter() {
+            match Instrumentation::read(s.as_bytes(), Path::new("test")) {
+                Err(ParseError(d)) => {
+                    let d = d.to_string();
+                    let location = format!("--> test:{}:{}", line, column);
+                    assert_eq!(d.lines().next(), Some(*reason));
+                    assert!(d.lines().nth(1).unwrap().ends_with(&location));
+                }
+                r => panic!("unexpected {:?}", r),
+            }
+        }
+    }
+
     #[test]
     fn test_parse_binding() {
         let s = "a b";
@@ -168,7 +1099,83 @@ mod tests {
 
         assert_eq!(r, "");
         assert_eq!(l.0, Instrument::from("a"));
-        assert_eq!(l.1, SampleFile::from("b"));
+        assert_eq!(l.1, Binding::new(SampleFile::from("b")));
+
+        let (_, (_, b)) = parse_binding("a b pan=-0.5 choke=c").unwrap();
+        assert_eq!(b.layers()[0].sample_file(), &SampleFile::from("b"));
+        assert_eq!(b.pan(), Pan::new(-0.5).unwrap());
+        assert_eq!(b.choke(), Some("c"));
+        assert_eq!(b.to_string(), "b pan=-0.5 choke=c");
+
+        let (_, (_, b)) = parse_binding("a b gain=-6 pitch=12 tune=-0").unwrap();
+        assert_eq!(b.gain(), -6.0);
+        assert_eq!(b.rate(), 2.0);
+        assert_eq!(b, {
+            let (_, (_, c)) = parse_binding("a b pitch=12 gain=-6.0").unwrap();
+            c
+        });
+        assert_eq!(b.to_string(), "b gain=-6 pitch=12");
+
+        let s = "a b,c@0.5,d@0.5 select=random";
+        let (_, (_, b)) = parse_binding(s).unwrap();
+        assert_eq!(b.layers().len(), 3);
+        assert_eq!(b.layers()[1].sample_file(), &SampleFile::from("c"));
+        assert_eq!(b.layers()[2].velocity(), 0.5);
+        assert_eq!(b.select(), Select::Random);
+        assert_eq!(b.velocity_layers(), vec![(0.0, vec![0]), (0.5, vec![1, 2])]);
+        assert_eq!(b.to_string(), &s[2..]);
+
+        let s = "a b start=10 end=200.5 reverse attack=2 decay=150 release=20";
+        let (_, (_, b)) = parse_binding(s).unwrap();
+        assert_eq!(b.start(), 10.0);
+        assert_eq!(b.end(), Some(200.5));
+        assert!(b.reverse());
+        assert_eq!((b.attack(), b.decay(), b.release()), (2.0, 150.0, 20.0));
+        assert_eq!(b.to_string(), &s[2..]);
+    }
+
+    #[test]
+    fn test_binding_setters() {
+        let mut b = Binding::new(SampleFile::from("b"));
+        b.set_gain(-6.0).unwrap();
+        b.set_pitch(12.0).unwrap();
+        b.set_tune(-0.0).unwrap();
+
+        assert_eq!(b, parse_binding("a b gain=-6 pitch=12").unwrap().1 .1);
+        assert_eq!(b.to_string(), "b gain=-6 pitch=12");
+        assert!(matches!(b.set_gain(20.0), Err(InvalidParameterError(_))));
+        assert!(b.set_pitch(f32::NAN).is_err());
+        assert!(b.set_tune(101.0).is_err());
+        assert_eq!((b.gain(), b.pitch(), b.tune()), (-6.0, 12.0, 0.0));
+
+        let s = "a b start=10 end=200.5 reverse attack=2 decay=150 release=20";
+        let mut b = Binding::new(SampleFile::from("b"));
+        b.set_start(10.0).unwrap();
+        b.set_end(Some(200.5)).unwrap();
+        b.set_reverse(true);
+        b.set_attack(2.0).unwrap();
+        b.set_decay(150.0).unwrap();
+        b.set_release(20.0).unwrap();
+
+        assert_eq!(b, parse_binding(s).unwrap().1 .1);
+        assert!(b.set_start(-1.0).is_err());
+        assert!(b.set_end(Some(60_001.0)).is_err());
+        assert!(b.set_release(f32::NAN).is_err());
+        assert_eq!((b.start(), b.end(), b.release()), (10.0, Some(200.5), 20.0));
+        assert!(b.set_start(200.5).is_err());
+        assert!(b.set_end(Some(10.0)).is_err());
+        assert_eq!((b.start(), b.end()), (10.0, Some(200.5)));
+        b.set_end(None).unwrap();
+        b.set_reverse(false);
+        assert_eq!(b.to_string(), "b start=10 attack=2 decay=150 release=20");
+    }
+
+    #[test]
+    fn test_parse_parameter() {
+        assert_eq!(parse_parameter("pan=1 x").unwrap(), (" x", ("pan", "1")));
+        assert!(parse_parameter("pan").is_err());
+        assert!(parse_parameter("pan=").is_err());
+        assert!(parse_parameter("=1").is_err());
     }
 
     #[test]
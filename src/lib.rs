@@ -0,0 +1,456 @@
+//! *rudiments* is a step-sequencing drum machine that plays rhythm patterns using
+//! audio samples.
+//!
+//! # Features
+//!
+//! - Programmable measures of any number of steps.
+//! - Any time signature.
+//! - Configurable per-track amplitude.
+//! - Stereo output with per-instrument panning.
+//! - Choke groups (e.g. an open hi-hat cut off by a closed one).
+//! - Per-instrument gain, pitch and tuning.
+//! - Sample trimming, reversing and fade envelopes.
+//! - Velocity-layered and round-robin sample sets.
+//! - Per-step accents, ghost notes and velocities.
+//! - Adjustable tempo.
+//! - Adjustable swing.
+//! - Playback once, a set number of times, for a set duration, or on repeat.
+//! - Song mode that arranges patterns into a sequence.
+//! - Count-in and metronome click track.
+//! - Live reload of edited files during playback.
+//! - Interactive terminal step sequencer editor.
+//! - Live playhead display in the terminal.
+//! - Offline rendering to a WAV file.
+//! - Usable as a library.
+//! - Supports several audio file formats.
+//!     - MP3
+//!     - WAV
+//!     - Vorbis
+//!     - Flac
+//!
+//! Playback and audio file decoding are handled by [rodio](https://github.com/RustAudio/rodio).
+//!
+//! # Usage
+//!
+//! ```text
+//! rudiments 0.1.0
+//! A step-sequencing drum machine
+//!
+//! USAGE:
+//!     rudiments [FLAGS] [OPTIONS] --instrumentation <FILE> --samples <DIRECTORY> <--pattern <FILE>|--song <FILE>>
+//!
+//! FLAGS:
+//!     -e, --edit         Edit the pattern in an interactive step sequencer while it plays
+//!     -h, --help         Prints help information
+//!     -m, --metronome    Click on every beat throughout playback
+//!         --playhead     Show the pattern grid with the playhead moving along it
+//!     -r, --repeat       Repeat the pattern or song until stopped
+//!         --strict       Treat unbound instruments and missing sample files as errors
+//!     -V, --version      Prints version information
+//!     -w, --watch        Play until stopped and reload the files whenever they change
+//!
+//! OPTIONS:
+//!         --bit-depth <NUMBER>         Bits per sample of the rendered file [default: 16]
+//!                                      [possible values: 8, 16, 24, 32]
+//!         --click <FILE>               Audio file to click with instead of the synthesised
+//!                                      click
+//!         --click-level <AMPLITUDE>    Amplitude of the click [default: 1]
+//!         --count-in <BARS>            Number of bars of click to count in before the
+//!                                      pattern or song [default: 0]
+//!     -d, --duration <LENGTH>          Stop after a number of seconds (e.g. 30s) or bars
+//!                                      (e.g. 8bars)
+//!     -i, --instrumentation <FILE>     Path to instrumentation file
+//!     -l, --loops <NUMBER>             Number of pattern or song iterations to play or
+//!                                      render [default: 1, or as many as the duration
+//!                                      takes]
+//!     -o, --render <FILE>              Render to a WAV file instead of playing
+//!     -p, --pattern <FILE>             Path to pattern file
+//!     -s, --samples <DIRECTORY>        Search path for sample files
+//!         --sample-rate <NUMBER>       Sample rate of the rendered file [default: 44100]
+//!         --song <FILE>                Path to song file, instead of a pattern file
+//!         --swing <PERCENT>            Delay of off-beat steps as a percentage of a step
+//!                                      [default: 0]
+//!     -t, --tempo <NUMBER>             Playback tempo [default: 120]
+//! ```
+//!
+//! ## Inputs
+//!
+//! rudiments loads a *pattern* file and binds the pattern's tracks to audio files
+//! in a *samples* directory per an *instrumentation* file.
+//! Both files may contain blank lines and comments. A comment starts with `#` and
+//! runs to the end of the line, so instrument names can't contain `#`.
+//!
+//! Mistakes in either file are reported with the file name, line, and column of the
+//! offending part of the line, along with what was expected.
+//!
+//! ```text
+//! error: beat of 3 steps, expected 4
+//!  --> patterns/groove:3:24
+//!   |
+//! 3 | snare  |----|x---|----|x--|
+//!   |                        ^^^
+//! ```
+//!
+//! Before playback, rudiments warns about pattern instruments that are not bound to
+//! a sample file (and so would be silent) and about sample files that are missing
+//! from the samples directory, which are left out so that the rest still plays.
+//! With `--strict` these are errors instead.
+//!
+//! ### Pattern file (`--pattern`)
+//!
+//! Each line of a pattern file represents a track. There is no limit to the number
+//! of tracks in a pattern. A track contains an instrument name, a step sequence,
+//! and an optional amplitude. The instrument name is an identifier and can only
+//! appear once per pattern. Each sequence represents one or more measures
+//! divided into equally long steps (`x` for *play* and `-` for *silent*).
+//! The number of steps is up to the pattern (16 steps make 16th notes, 12 steps
+//! make 8th note triplets, and so on), but every track of a pattern must have
+//! the same number of steps per measure. Steps may be grouped into beats with `|`
+//! separators, in which case there must be one equally long group per beat.
+//! A sequence spans as many measures as it has groups of beats (a sequence
+//! without separators spans a single measure). Tracks that span fewer measures
+//! than the longest track are repeated to fill the pattern, and the whole pattern
+//! is looped.
+//! A played step may also be accented (`X`), played as a ghost note (`o`), or
+//! given an explicit velocity from `1` (softest) to `9` (as loud as `x`).
+//! A track may optionally include an amplitude in the range of [0,1] inclusive.
+//! By default, a track plays at full volume: a regular step (`x`) plays at the
+//! track's amplitude, a ghost note at 0.375 times it, and an accent at 1.25 times
+//! it.
+//!
+//! This is an example of a pattern file's contents for a standard 8th note groove
+//! with the hi-hat track played at half volume.
+//!
+//! ```text
+//! hi-hat |x-x-|x-x-|x-x-|x-x-| 0.5
+//! snare  |----|x---|----|x---|
+//! kick   |x---|----|x---|----|
+//! ```
+//!
+//! This is an example of a funk groove with accented hi-hats and ghosted snare
+//! notes.
+//!
+//! ```text
+//! hi-hat |X-x-|X-x-|X-x-|X-x-| 0.5
+//! snare  |----|x--o|-o--|x--o|
+//! kick   |x-x-|----|--x-|----|
+//! ```
+//!
+//! This is an example of a shuffle played with 8th note triplets.
+//!
+//! ```text
+//! hi-hat |x-x|x-x|x-x|x-x|
+//! snare  |---|x--|---|x--|
+//! kick   |x--|--x|x--|---|
+//! ```
+//!
+//! This is an example of a two measure phrase with a fill on the second measure.
+//! The hi-hat track spans a single measure and is played in both measures.
+//!
+//! ```text
+//! hi-hat |x-x-|x-x-|x-x-|x-x-|
+//! snare  |----|x---|----|x---|----|x---|----|xxxx|
+//! kick   |x---|----|x---|----|x---|----|x-x-|----|
+//! ```
+//!
+//! Measures are in 4/4 time unless the pattern file starts with a time signature
+//! header. This is an example of a pattern in 7/8 time.
+//!
+//! ```text
+//! time 7/8
+//! hi-hat |x-|x-|x-|x-|x-|x-|x-|
+//! snare  |--|--|x-|--|--|x-|--|
+//! kick   |x-|x-|--|x-|x-|--|--|
+//! ```
+//!
+//! A pattern file may also start with a swing header (e.g. `swing 33%`) that
+//! overrides the playback swing (see `--swing`).
+//!
+//! ### Instrumentation file (`--instrumentation`)
+//!
+//! An instrumentation file binds the instruments from a pattern file to audio
+//! sample files. Each line of an instrumentation file contains an instrument name
+//! and an audio file name. Each instrument may only appear once, but a single
+//! audio file may be bound to multiple instruments.
+//!
+//! A binding may be followed by parameters of the form `key=value`:
+//!
+//! - `pan` places the instrument in the stereo field, from `-1` (hard left) to
+//!   `1` (hard right). Instruments are centred by default. Mono sample files are
+//!   panned with a constant-power pan law, and stereo sample files keep their
+//!   channels with the pan acting as a balance.
+//! - `choke` puts the instrument in the named choke group. Triggering any
+//!   instrument of a choke group quickly fades out the still-sounding hits of
+//!   the group, like a closed hi-hat cutting off an open one.
+//! - `gain` adjusts the level of the instrument in decibels, from `-60` to `12`.
+//! - `pitch` shifts the instrument by a number of semitones, from `-24` to `24`,
+//!   by resampling, so it also plays shorter when higher and longer when lower.
+//! - `tune` fine tunes the instrument in cents, from `-100` to `100`.
+//! - `start` and `end` trim the sample file to the offsets given in milliseconds.
+//!   The end must come after the start, and a start past the end of the sample
+//!   file is warned about before playback (an error with `--strict`).
+//! - `reverse` plays the sample file backwards. It is a flag and takes no value.
+//! - `attack` fades the sample file in over the milliseconds given, `decay` makes
+//!   it fall by 60 dB over the milliseconds given after the attack, and `release`
+//!   fades it out over the milliseconds given before its end.
+//!
+//! Times are of the sample file before any pitch shift, and apply after trimming
+//! and reversing.
+//!
+//! An instrument may also be bound to a comma-separated set of audio files, so
+//! repeated hits don't all sound the same. Each audio file may be followed by `@`
+//! and the smallest velocity of the steps it plays, from `0` to `1.25` (an
+//! accent), which layers the set by velocity. Each step plays the loudest layer
+//! its velocity reaches, and the audio files of a layer take turns:
+//!
+//! - `select=cycle` (the default) plays them in the order they are bound.
+//! - `select=random` plays them at random, never the same one twice in a row, and
+//!   differently on every run.
+//!
+//! This binds a snare to a soft layer for ghost notes and two alternating
+//! recordings for every other step.
+//!
+//! ```text
+//! snare  sd-ghost.wav,sd-1.wav@0.5,sd-2.wav@0.5
+//! ```
+//!
+//! This is an example of an instrumentation file's contents that binds five
+//! instruments to four audio sample files, with the toms panned apart and the
+//! second tom tuned a fourth higher.
+//!
+//! > Note that `tom.wav` is used for both `tom-1` and `tom-2`.
+//!
+//! ```text
+//! hi-hat hh.wav
+//! tom-1  tom.wav pan=-0.5
+//! tom-2  tom.wav pan=0.5 pitch=5
+//! snare  snare.wav
+//! kick   kick.wav
+//! ```
+//!
+//! ### Song file (`--song`)
+//!
+//! A song file arranges pattern files into a sequence of sections that are played
+//! back to back, in place of a single `--pattern`. Each line of a song file
+//! contains the path of a pattern file, relative to the song file, and an optional
+//! repeat count. By default, a section is played once. Every pattern of a song is
+//! bound with the same instrumentation file and samples directory.
+//!
+//! This is an example of a song file's contents that plays an intro, a verse four
+//! times, a fill, and then a chorus eight times.
+//!
+//! ```text
+//! intro  x1
+//! verse  x4
+//! fill
+//! chorus x8
+//! ```
+//!
+//! ### Samples directory (`--samples`)
+//!
+//! rudiments will look in the samples directory for the audio files listed in the
+//! instrumentation file.
+//!
+//! ### Tempo (`--tempo`)
+//!
+//! This adjusts the playback tempo (aka beats per minute). The default playback
+//! tempo is 120.
+//! The tempo is always counted in quarter notes, so a measure of 6/8 lasts as long
+//! as three beats.
+//!
+//! ### Swing (`--swing`)
+//!
+//! This delays every second step within each beat by a percentage of a step,
+//! giving straight 16th note patterns a shuffled feel. A swing of 0% plays the
+//! steps straight, and a swing of 33% approximates a triplet shuffle. Patterns
+//! whose measures don't split evenly into beats are played straight. The swing
+//! header of a pattern file takes precedence over this option.
+//!
+//! ### Loops and duration (`--loops`, `--duration`)
+//!
+//! By default, rudiments plays the pattern or song once. `--loops` plays it a set
+//! number of times, and `--repeat` plays it until stopped. `--duration` plays it
+//! for a number of seconds (e.g. `30s`, or just `30`) or bars (e.g. `8bars`),
+//! counting measures across the sections of a song and looping as often as it
+//! takes, unless `--loops` or `--repeat` is also given, in which case playback ends
+//! at whichever comes first. Either way, the tails of the final hits are allowed to
+//! ring out. Neither a loop count nor a duration of zero is accepted.
+//!
+//! ### Click (`--count-in`, `--metronome`)
+//!
+//! rudiments can play a click track independently of the pattern, to count in a
+//! number of bars before playback (`--count-in`) and/or to keep time on every beat
+//! throughout playback (`--metronome`). The click sounds on every beat of the
+//! measure's time signature and accents the first beat of each measure. The click
+//! is synthesised unless an audio file is given with `--click`, and its amplitude is
+//! set with `--click-level`.
+//!
+//! ### Playhead (`--playhead`)
+//!
+//! During playback, rudiments can show the grid of the pattern being played with
+//! the current step highlighted, along with the current bar (and section of a song)
+//! and the tempo. The playhead follows the audio clock, so it can be used as a
+//! visual guide while jamming. Press `q` to stop.
+//!
+//! ### Editor (`--edit`)
+//!
+//! rudiments can also edit a pattern file in an interactive step sequencer in the
+//! terminal while the pattern loops. The editor shows a grid with a row of steps
+//! per instrument, and every edit is heard from the next measure on.
+//!
+//! | Key                 | Action                                  |
+//! | ------------------- | --------------------------------------- |
+//! | arrows or `h/j/k/l` | Move between tracks and steps           |
+//! | space or enter      | Toggle the step                         |
+//! | `+` / `-`           | Raise or lower the track's amplitude    |
+//! | `>` / `<`           | Raise or lower the tempo                |
+//! | `a`                 | Audition the track's sample             |
+//! | `s`                 | Save the pattern back to its file       |
+//! | `q`, escape or `^C` | Quit (twice if there are unsaved edits) |
+//!
+//! ### Watch (`--watch`)
+//!
+//! In watch mode, rudiments plays the pattern or song until stopped and reloads the
+//! pattern, song, and instrumentation files whenever they change, so they can be
+//! edited in a text editor while listening. The new mix is swapped in at the next
+//! measure boundary without interrupting playback. If a file fails to parse, the
+//! error is reported and the previous mix carries on playing.
+//!
+//! ### Render (`--render`)
+//!
+//! Instead of playing through the default audio device, rudiments can write the mix
+//! to a WAV file. The pattern is laid out back to back as it would be played, for
+//! `--loops` times and/or `--duration`, and the tails of the final hits are allowed
+//! to ring out. A render can't `--repeat`. The sample rate and bit depth of the
+//! file are set with `--sample-rate` and `--bit-depth`.
+//!
+//! ```bash
+//! $ rudiments \
+//!   --pattern ./assets/patterns/standard \
+//!   --instrumentation ./assets/instrumentations/linndrum \
+//!   --samples ./assets/samples/linndrum \
+//!   --render standard.wav \
+//!   --loops 4
+//! ```
+//!
+//! # Library
+//!
+//! rudiments is also a library crate, so the patterns, instrumentations, playback
+//! and rendering behind the command line can be used from other programs.
+//! Patterns can also be built and edited in code before they are played,
+//! rendered, or saved back to pattern files.
+//!
+//! ```no_run
+//! # fn main() -> rudiments::Result<()> {
+//! use rudiments::{audio, Instrumentation, Pattern, Swing};
+//! use std::path::Path;
+//!
+//! let pattern = Pattern::parse(Path::new("assets/patterns/standard"))?;
+//! let instrumentation = Instrumentation::parse(Path::new("assets/instrumentations/linndrum"))?;
+//! audio::render(
+//!     pattern,
+//!     instrumentation,
+//!     Path::new("assets/samples/linndrum"),
+//!     "120".parse::<audio::Tempo>()?,
+//!     Swing::default(),
+//!     Path::new("standard.wav"),
+//!     &audio::RenderSettings::default(),
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Installation
+//!
+//! rudiments can be installed with `cargo`.
+//!
+//! ```bash
+//! $ cargo install rudiments
+//! ```
+//!
+//! # Demos
+//!
+//! The `assets` directory contains several example patterns as well as audio samples
+//! from the [LinnDrum](https://en.wikipedia.org/wiki/LinnDrum) drum machine.
+//!
+//! ## Standard 8th note groove
+//!
+//! ```bash
+//! $ rudiments \
+//!   --pattern ./assets/patterns/standard \
+//!   --instrumentation ./assets/instrumentations/linndrum \
+//!   --samples ./assets/samples/linndrum \
+//!   --repeat
+//! ```
+//!
+//! ## Standard 8th note groove with a fill
+//!
+//! ```bash
+//! $ rudiments \
+//!   --song ./assets/songs/standard \
+//!   --instrumentation ./assets/instrumentations/linndrum \
+//!   --samples ./assets/samples/linndrum \
+//!   --repeat
+//! ```
+//!
+//! ## Burning Up (Madonna)
+//!
+//! ```bash
+//! $ rudiments \
+//!   --pattern ./assets/patterns/burning-up \
+//!   --instrumentation ./assets/instrumentations/linndrum \
+//!   --samples ./assets/samples/linndrum \
+//!   --tempo 140 \
+//!   --repeat
+//! ```
+//!
+//! ## Thriller (Michael Jackson)
+//!
+//! ```bash
+//! $ rudiments \
+//!   --pattern ./assets/patterns/thriller \
+//!   --instrumentation ./assets/instrumentations/linndrum \
+//!   --samples ./assets/samples/linndrum \
+//!   --tempo 118 \
+//!   --repeat
+//! ```
+//!
+//! ## Get a Little (Patrick Cowley)
+//!
+//! ```bash
+//! $ rudiments \
+//!   --pattern ./assets/patterns/get-a-little \
+//!   --instrumentation ./assets/instrumentations/linndrum \
+//!   --samples ./assets/samples/linndrum \
+//!   --repeat
+//! ```
+//!
+//! ## Tom Sawyer (Rush)
+//!
+//! ```bash
+//! $ rudiments \
+//!   --pattern ./assets/patterns/tom-sawyer \
+//!   --instrumentation ./assets/instrumentations/linndrum \
+//!   --samples ./assets/samples/linndrum \
+//!   --tempo 180
+//! ```
+
+pub use crate::{
+    audio::{play, render, Click, Length, PlaySettings, Player, RenderSettings, Tempo},
+    diagnostic::Diagnostic,
+    error::{Error, Result},
+    instrumentation::{Binding, Instrumentation, Layer, Pan, Problem, SampleFile, Select},
+    pattern::{Amplitude, Instrument, Pattern, Steps, Swing, TimeSignature, Velocity},
+    song::{Section, Song},
+};
+
+pub mod audio;
+mod comment;
+mod diagnostic;
+mod display;
+pub mod editor;
+pub mod error;
+pub mod instrumentation;
+pub mod pattern;
+mod sequencer;
+pub mod song;
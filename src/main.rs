@@ -1,169 +1,21 @@
-//! *rudiments* is a step-sequencing drum machine that plays rhythm patterns using
-//! audio samples.
+//! The rudiments command line interface.
 //!
-//! # Features
-//!
-//! - 16-step programmable measures.
-//! - Configurable per-track amplitude.
-//! - Adjustable tempo.
-//! - Playback once or on repeat.
-//! - Supports several audio file formats.
-//!     - MP3
-//!     - WAV
-//!     - Vorbis
-//!     - Flac
-//!
-//! Playback and audio file decoding are handled by [rodio](https://github.com/RustAudio/rodio).
-//!
-//! # Usage
-//!
-//! ```text
-//! rudiments 0.1.0
-//! A step-sequencing drum machine
-//!
-//! USAGE:
-//!     rudiments [FLAGS] [OPTIONS] --pattern <FILE> --instrumentation <FILE> --samples <DIRECTORY>
-//!
-//! FLAGS:
-//!     -h, --help       Prints help information
-//!     -r, --repeat     Repeat the pattern until stopped
-//!     -V, --version    Prints version information
-//!
-//! OPTIONS:
-//!     -i, --instrumentation <FILE>    Path to instrumentation file
-//!     -p, --pattern <FILE>            Path to pattern file
-//!     -s, --samples <DIRECTORY>       Search path for sample files
-//!     -t, --tempo <NUMBER>            Playback tempo [default: 120]
-//! ```
-//!
-//! ## Inputs
-//!
-//! rudiments loads a *pattern* file and binds the pattern's tracks to audio files
-//! in a *samples* directory per an *instrumentation* file.
-//!
-//! ### Pattern file (`--pattern`)
-//!
-//! Each line of a pattern file represents a track. There is no limit to the number
-//! of tracks in a pattern. A track contains an instrument name, a 16-step sequence,
-//! and an optional amplitude. The instrument name is an identifier and can only
-//! appear once per pattern. Each sequence represents a single measure in 4/4 time
-//! divided into 16th note steps (`x` for *play* and `-` for *silent*).
-//! A track may optionally include an amplitude in the range of [0,1] inclusive.
-//! By default, a track plays at full volume.
-//!
-//! This is an example of a pattern file's contents for a standard 8th note groove
-//! with the hi-hat track played at half volume.
-//!
-//! ```text
-//! hi-hat |x-x-|x-x-|x-x-|x-x-| 0.5
-//! snare  |----|x---|----|x---|
-//! kick   |x---|----|x---|----|
-//! ```
-//!
-//! ### Instrumentation file (`--instrumentation`)
-//!
-//! An instrumentation file binds the instruments from a pattern file to audio
-//! sample files. Each line of an instrumentation file contains an instrument name
-//! and an audio file name. Each instrument may only appear once, but a single
-//! audio file may be bound to multiple instruments.
-//!
-//! This is an example of an instrumentation file's contents that binds five
-//! instruments to four audio sample files.
-//!
-//! > Note that `tom.wav` is used for both `tom-1` and `tom-2`.
-//!
-//! ```text
-//! hi-hat hh.wav
-//! tom-1  tom.wav
-//! tom-2  tom.wav
-//! snare  snare.wav
-//! kick   kick.wav
-//! ```
-//!
-//! ### Samples directory (`--samples`)
-//!
-//! rudiments will look in the samples directory for the audio files listed in the
-//! instrumentation file.
-//!
-//! ### Tempo (`--tempo`)
-//!
-//! This adjusts the playback tempo (aka beats per minute). The default playback
-//! tempo is 120.
-//!
-//! # Installation
-//!
-//! rudiments can be installed with `cargo`.
-//!
-//! ```bash
-//! $ cargo install rudiments
-//! ```
-//!
-//! # Demos
-//!
-//! The `assets` directory contains several example patterns as well as audio samples
-//! from the [LinnDrum](https://en.wikipedia.org/wiki/LinnDrum) drum machine.
-//!
-//! ## Standard 8th note groove
-//!
-//! ```bash
-//! $ rudiments \
-//!   --pattern ./assets/patterns/standard \
-//!   --instrumentation ./assets/instrumentations/linndrum \
-//!   --samples ./assets/samples/linndrum \
-//!   --repeat
-//! ```
-//!
-//! ## Burning Up (Madonna)
-//!
-//! ```bash
-//! $ rudiments \
-//!   --pattern ./assets/patterns/burning-up \
-//!   --instrumentation ./assets/instrumentations/linndrum \
-//!   --samples ./assets/samples/linndrum \
-//!   --tempo 140 \
-//!   --repeat
-//! ```
-//!
-//! ## Thriller (Michael Jackson)
-//!
-//! ```bash
-//! $ rudiments \
-//!   --pattern ./assets/patterns/thriller \
-//!   --instrumentation ./assets/instrumentations/linndrum \
-//!   --samples ./assets/samples/linndrum \
-//!   --tempo 118 \
-//!   --repeat
-//! ```
-//!
-//! ## Get a Little (Patrick Cowley)
-//!
-//! ```bash
-//! $ rudiments \
-//!   --pattern ./assets/patterns/get-a-little \
-//!   --instrumentation ./assets/instrumentations/linndrum \
-//!   --samples ./assets/samples/linndrum \
-//!   --repeat
-//! ```
-//!
-//! ## Tom Sawyer (Rush)
-//!
-//! ```bash
-//! $ rudiments \
-//!   --pattern ./assets/patterns/tom-sawyer \
-//!   --instrumentation ./assets/instrumentations/linndrum \
-//!   --samples ./assets/samples/linndrum \
-//!   --tempo 180
-//! ```
+//! See the library crate for documentation of the inputs and options.
 
 use clap::Parser;
-use std::path::Path;
+use std::{
+    fs, iter,
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, SystemTime},
+};
 
-use crate::{error::Result, instrumentation::Instrumentation, pattern::Pattern};
+use rudiments::{
+    audio, editor, Amplitude, Error::ValidationError, Instrumentation, Pattern, Result, Song, Swing,
+};
 
-mod audio;
-mod error;
-mod instrumentation;
-mod pattern;
+/// Interval at which watched files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
 
 /// A step-sequencing drum machine
 #[derive(Parser, Debug)]
@@ -171,11 +23,25 @@ mod pattern;
 struct Opts {
     /// Playback tempo
     #[clap(short, long, value_name = "NUMBER", default_value = "120")]
-    tempo: u16,
+    tempo: audio::Tempo,
+
+    /// Delay of off-beat steps as a percentage of a step
+    #[clap(long, value_name = "PERCENT", default_value = "0")]
+    swing: Swing,
 
     /// Path to pattern file
-    #[clap(short, long, value_name = "FILE")]
-    pattern: String,
+    #[clap(
+        short,
+        long,
+        value_name = "FILE",
+        required_unless_present = "song",
+        conflicts_with = "song"
+    )]
+    pattern: Option<String>,
+
+    /// Path to song file, instead of a pattern file
+    #[clap(long, value_name = "FILE")]
+    song: Option<String>,
 
     /// Path to instrumentation file
     #[clap(short, long, value_name = "FILE")]
@@ -185,23 +51,241 @@ struct Opts {
     #[clap(short, long, value_name = "DIRECTORY")]
     samples: String,
 
-    /// Repeat the pattern until stopped
-    #[clap(short, long)]
+    /// Repeat the pattern or song until stopped
+    #[clap(short, long, conflicts_with_all = &["loops", "render"])]
     repeat: bool,
+
+    /// Edit the pattern in an interactive step sequencer while it plays
+    #[clap(
+        short,
+        long,
+        requires = "pattern",
+        conflicts_with_all = &["song", "render", "watch", "loops", "duration", "repeat"]
+    )]
+    edit: bool,
+
+    /// Play until stopped and reload the files whenever they change
+    #[clap(
+        short,
+        long,
+        conflicts_with_all = &["render", "loops", "duration"]
+    )]
+    watch: bool,
+
+    /// Show the pattern grid with the playhead moving along it
+    #[clap(long, conflicts_with_all = &["render", "watch", "edit"])]
+    playhead: bool,
+
+    /// Treat unbound instruments and missing sample files as errors
+    #[clap(long)]
+    strict: bool,
+
+    /// Number of bars of click to count in before the pattern or song
+    #[clap(long, value_name = "BARS", default_value = "0")]
+    count_in: usize,
+
+    /// Click on every beat throughout playback
+    #[clap(short, long)]
+    metronome: bool,
+
+    /// Audio file to click with instead of the synthesised click
+    #[clap(long, value_name = "FILE")]
+    click: Option<String>,
+
+    /// Amplitude of the click
+    #[clap(long, value_name = "AMPLITUDE", default_value = "1")]
+    click_level: Amplitude,
+
+    /// Render to a WAV file instead of playing
+    #[clap(short = 'o', long, value_name = "FILE")]
+    render: Option<String>,
+
+    /// Number of pattern or song iterations to play or render [default: 1, or as
+    /// many as the duration takes]
+    #[clap(short, long, value_name = "NUMBER")]
+    loops: Option<u32>,
+
+    /// Stop after a number of seconds (e.g. 30s) or bars (e.g. 8bars)
+    #[clap(short, long, value_name = "LENGTH")]
+    duration: Option<audio::Length>,
+
+    /// Sample rate of the rendered file
+    #[clap(long, value_name = "NUMBER", default_value = "44100")]
+    sample_rate: u32,
+
+    /// Bits per sample of the rendered file
+    #[clap(
+        long,
+        value_name = "NUMBER",
+        default_value = "16",
+        possible_values = &["8", "16", "24", "32"]
+    )]
+    bit_depth: u16,
 }
 
-fn main() -> Result<()> {
+fn main() {
     let opts: Opts = Opts::parse();
-    let pattern = Pattern::parse(Path::new(&opts.pattern))?;
-    let instrumentation = Instrumentation::parse(Path::new(&opts.instrumentation))?;
+    if let Err(e) = run(opts) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Plays or renders a pattern or song as directed by the options given.
+fn run(opts: Opts) -> Result<()> {
+    let (song, instrumentation) = load(&opts)?;
+    let click = audio::Click {
+        count_in: opts.count_in,
+        metronome: opts.metronome,
+        level: opts.click_level,
+        sample: opts.click.as_ref().map(PathBuf::from),
+    };
+
+    if let (true, Some(pattern)) = (opts.edit, &opts.pattern) {
+        editor::edit(
+            Path::new(pattern),
+            instrumentation,
+            Path::new(&opts.samples),
+            opts.tempo,
+            opts.swing,
+            &click,
+        )?;
+    } else if opts.watch {
+        watch(&opts, song, instrumentation, &click)?;
+    } else if let Some(render) = &opts.render {
+        audio::render(
+            song,
+            instrumentation,
+            Path::new(&opts.samples),
+            opts.tempo,
+            opts.swing,
+            Path::new(render),
+            &audio::RenderSettings {
+                loops: loops(&opts),
+                length: opts.duration,
+                click,
+                sample_rate: opts.sample_rate,
+                bit_depth: opts.bit_depth,
+            },
+        )?;
+    } else {
+        audio::play(
+            song,
+            instrumentation,
+            Path::new(&opts.samples),
+            opts.tempo,
+            opts.swing,
+            &audio::PlaySettings {
+                loops: loops(&opts),
+                length: opts.duration,
+                click,
+                playhead: opts.playhead,
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns the number of times to loop the pattern or song per the options
+/// given: until stopped when repeating, until the duration when one is given
+/// without a number of loops, and once otherwise.
+fn loops(opts: &Opts) -> Option<u32> {
+    match (opts.repeat, opts.loops, opts.duration) {
+        (true, _, _) | (false, None, Some(_)) => None,
+        (false, loops, _) => Some(loops.unwrap_or(1)),
+    }
+}
+
+/// Parses the pattern or song and the instrumentation named by the options
+/// given, and reports any problems binding them. Unless strict, sample files
+/// that are missing are left out so that the rest can still be played.
+fn load(opts: &Opts) -> Result<(Song, Instrumentation)> {
+    let song = match (&opts.song, &opts.pattern) {
+        (Some(song), _) => Song::parse(Path::new(song))?,
+        (None, pattern) => Song::from(Pattern::parse(Path::new(
+            pattern.as_deref().unwrap_or_default(),
+        ))?),
+    };
+    let mut instrumentation = Instrumentation::parse(Path::new(&opts.instrumentation))?;
 
-    audio::play(
-        pattern,
+    let mut problems = Vec::new();
+    for (pattern, _) in song.sections() {
+        for problem in instrumentation.validate(pattern, Path::new(&opts.samples)) {
+            if !problems.contains(&problem) {
+                problems.push(problem);
+            }
+        }
+    }
+    let severity = if opts.strict { "error" } else { "warning" };
+    for problem in problems.iter() {
+        eprintln!("{}: {}", severity, problem);
+    }
+    if opts.strict && !problems.is_empty() {
+        return Err(ValidationError(problems.len()));
+    }
+    instrumentation.remove_missing(Path::new(&opts.samples));
+
+    Ok((song, instrumentation))
+}
+
+/// Plays a pattern or song until stopped, and reloads it along with the
+/// instrumentation whenever their files change. Errors while reloading are
+/// reported and the previous mix carries on playing.
+fn watch(
+    opts: &Opts,
+    song: Song,
+    instrumentation: Instrumentation,
+    click: &audio::Click,
+) -> Result<()> {
+    let mut files = watched(opts, &song);
+    let mut times = modified(&files);
+    let player = audio::Player::start(
+        song,
         instrumentation,
         Path::new(&opts.samples),
-        audio::Tempo::from(opts.tempo),
-        opts.repeat,
+        opts.tempo,
+        opts.swing,
+        click,
     )?;
 
-    Ok(())
+    loop {
+        thread::sleep(WATCH_INTERVAL);
+        let latest = modified(&files);
+        if latest == times {
+            continue;
+        }
+        times = latest;
+
+        match load(opts) {
+            Ok((song, instrumentation)) => {
+                files = watched(opts, &song);
+                times = modified(&files);
+                match player.swap(song, instrumentation) {
+                    Ok(()) => eprintln!("reloaded"),
+                    Err(e) => eprintln!("error: {}", e),
+                }
+            }
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+}
+
+/// Returns the files a pattern or song was loaded from per the options given.
+fn watched(opts: &Opts, song: &Song) -> Vec<PathBuf> {
+    opts.song
+        .iter()
+        .chain(opts.pattern.iter())
+        .chain(iter::once(&opts.instrumentation))
+        .map(PathBuf::from)
+        .chain(song.paths().iter().cloned())
+        .collect()
+}
+
+/// Returns the last modification times of the files given, if known.
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
 }
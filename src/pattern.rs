@@ -1,50 +1,98 @@
 extern crate nom;
 
-use bitvec::{prelude::*, slice::Iter};
+use indexmap::{
+    map::{Entry, Keys},
+    IndexMap,
+};
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
-    character::complete::space0,
-    combinator::{all_consuming, opt, verify},
-    multi::fold_many1,
+    character::complete::{char, digit1, satisfy, space0, space1},
+    combinator::{all_consuming, consumed, map, opt, value},
+    multi::many0,
     number::complete::float,
-    IResult,
+    sequence::{separated_pair, terminated},
 };
 use std::{
     collections::HashMap,
     fmt,
-    fs::File,
+    fs::{self, File},
     io::{BufRead, BufReader},
+    ops::Range,
     path::Path,
+    slice::Iter,
+    str::FromStr,
+};
+
+use crate::{
+    comment::{self, COMMENT},
+    diagnostic::{fail, parse_end, span_of, Diagnostic, ParseResult},
+    error::{Error, Error::*, Result},
 };
 
-use crate::error::{Error::*, Result};
+/// The number of steps in a measure of a pattern without tracks.
+pub const DEFAULT_STEPS_PER_MEASURE: usize = 16;
 
-/// The number of steps in a measure.
-pub const STEPS_PER_MEASURE: usize = 16;
+/// The note values a time signature's beat unit may take.
+const BEAT_UNITS: [usize; 6] = [1, 2, 4, 8, 16, 32];
 
-/// The number of beats in a measure.
-pub const BEATS_PER_MEASURE: usize = 4;
+/// Introduces the time signature header of a pattern file.
+const TIME_SIGNATURE: &str = "time";
+
+/// Introduces the swing header of a pattern file.
+const SWING: &str = "swing";
 
 /// Indicates a *play* step.
-const STEP_PLAY: &str = "x";
+const STEP_PLAY: char = 'x';
+
+/// Indicates an *accented* step.
+const STEP_ACCENT: char = 'X';
+
+/// Indicates a *ghost* step.
+const STEP_GHOST: char = 'o';
 
 /// Indicates a *silent* step.
-const STEP_SILENT: &str = "-";
+const STEP_SILENT: char = '-';
 
 /// The beat separator in a step sequence.
-const SEPARATOR: &str = "|";
+const SEPARATOR: char = '|';
+
+/// The velocity of a regular step, which plays at the track's amplitude.
+const VELOCITY_NORMAL: f32 = 1.0;
+
+/// The velocity of an accented step, about 2 dB louder than a regular step.
+const VELOCITY_ACCENT: f32 = VELOCITY_NORMAL * 1.25;
+
+/// The velocity of a ghost step, about 8.5 dB softer than a regular step.
+const VELOCITY_GHOST: f32 = VELOCITY_NORMAL * 0.375;
 
 /// Reperesents the contents of a pattern file.
 ///
 /// Each line of a pattern file represents a track. There is no limit to the number
-/// of tracks in a pattern. A track contains an instrument name, a 16-step sequence,
+/// of tracks in a pattern. A track contains an instrument name, a step sequence,
 /// and an optional amplitude. The instrument name is an identifier and can only
-/// appear once per pattern. Each sequence represents a single measure in 4/4 time
-/// divided into 16th note steps (`x` for *play* and `-` for *silent*).
+/// appear once per pattern. Each sequence represents one or more measures
+/// divided into equally long steps (`x` for *play* and `-` for *silent*).
+/// The number of steps is up to the pattern (16 steps make 16th notes, 12 steps
+/// make 8th note triplets, and so on), but every track of a pattern must have
+/// the same number of steps per measure. Steps may be grouped into beats with `|`
+/// separators, in which case there must be one equally long group per beat.
+/// A sequence spans as many measures as it has groups of beats (a sequence
+/// without separators spans a single measure). Tracks that span fewer measures
+/// than the longest track are repeated to fill the pattern, and the whole pattern
+/// is looped.
+/// A played step may also be accented (`X`), played as a ghost note (`o`), or
+/// given an explicit velocity from `1` (softest) to `9` (loudest).
 /// A track may optionally include an amplitude in the range of [0,1] inclusive.
 /// By default, a track plays at full volume.
 ///
+/// Measures are in 4/4 time unless the pattern file starts with a time
+/// signature header (e.g. `time 7/8`). A pattern file may also start with a swing
+/// header (e.g. `swing 33%`) that overrides the playback swing.
+///
+/// Blank lines are ignored, and comments start with `#` and run to the end of
+/// the line. Comments are kept with the track that follows (or trails) them.
+///
 /// # Example
 ///
 /// This is an example of a pattern file's contents for a standard 8th note groove
@@ -55,51 +103,585 @@ const SEPARATOR: &str = "|";
 /// snare  |----|x---|----|x---|
 /// kick   |x---|----|x---|----|
 /// ```
-#[derive(Debug)]
-pub struct Pattern(HashMap<Instrument, (Steps, Amplitude)>);
+///
+/// This is an example of a funk groove with accented hi-hats and ghosted snare
+/// notes.
+///
+/// ```text
+/// hi-hat |X-x-|X-x-|X-x-|X-x-| 0.5
+/// snare  |----|x--o|-o--|x--o|
+/// kick   |x-x-|----|--x-|----|
+/// ```
+///
+/// This is an example of a shuffle played with 8th note triplets.
+///
+/// ```text
+/// hi-hat |x-x|x-x|x-x|x-x|
+/// snare  |---|x--|---|x--|
+/// kick   |x--|--x|x--|---|
+/// ```
+///
+/// This is an example of a two measure phrase with a fill on the second measure.
+/// The hi-hat track spans a single measure and is played in both measures.
+///
+/// ```text
+/// hi-hat |x-x-|x-x-|x-x-|x-x-|
+/// snare  |----|x---|----|x---|----|x---|----|xxxx|
+/// kick   |x---|----|x---|----|x---|----|x-x-|----|
+/// ```
+///
+/// This is an example of a pattern in 7/8 time.
+///
+/// ```text
+/// time 7/8
+/// hi-hat |x-|x-|x-|x-|x-|x-|x-|
+/// snare  |--|--|x-|--|--|x-|--|
+/// kick   |x-|x-|--|x-|x-|--|--|
+/// ```
+///
+/// Patterns may also be built and edited in code. This builds a four on the
+/// floor kick drum.
+///
+/// ```
+/// use rudiments::{Amplitude, Instrument, Pattern, Steps};
+///
+/// let mut pattern = Pattern::default();
+/// let kick = Instrument::from("kick");
+/// pattern.insert(kick.clone(), Steps::zeros(16), Amplitude::max())?;
+/// for step in (0..16).step_by(4) {
+///     pattern.toggle_step(&kick, step)?;
+/// }
+/// # Ok::<(), rudiments::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    tracks: IndexMap<Instrument, (Steps, Amplitude)>,
+    steps: usize,
+    measures: usize,
+    time_signature: TimeSignature,
+    swing: Option<Swing>,
+    comments: HashMap<Instrument, Comments>,
+    header: Vec<String>,
+    footer: Vec<String>,
+}
 
 impl Pattern {
+    /// Returns an empty pattern whose measures have the number of steps and
+    /// time signature given. A pattern has at least one step and one measure.
+    /// A pattern of several measures must divide its steps into beats, which is
+    /// how a pattern file tells measures apart.
+    pub fn new(steps: usize, measures: usize, time_signature: TimeSignature) -> Result<Pattern> {
+        if steps == 0 || measures == 0 {
+            return Err(EmptyPatternError(steps, measures));
+        }
+        check_beats(steps, measures, time_signature)?;
+
+        Ok(Pattern {
+            tracks: IndexMap::new(),
+            steps,
+            measures,
+            time_signature,
+            swing: None,
+            comments: HashMap::new(),
+            header: Vec::new(),
+            footer: Vec::new(),
+        })
+    }
+
+    /// Saves the pattern to a pattern file located at the path given.
+    pub fn save(&self, p: &Path) -> Result<()> {
+        fs::write(p, self.to_string())?;
+
+        Ok(())
+    }
+
     /// Parses a pattern file located at the path given.
     pub fn parse(p: &Path) -> Result<Pattern> {
         if !p.is_file() {
             return Err(FileDoesNotExistError(p.into()));
         }
         let f = File::open(p)?;
-        let r = BufReader::new(f);
-
-        let mut m: HashMap<Instrument, (Steps, Amplitude)> = HashMap::new();
-        for l in r.lines() {
-            let l = l?;
-            match parse_track(&l[..]) {
-                Ok((_, (i, s, a))) => match m.insert(i, (s, a)) {
-                    Some(_) => return Err(DuplicatePatternError(l)),
+
+        Pattern::read(BufReader::new(f), p)
+    }
+
+    /// Reads the contents of a pattern file from the reader given.
+    /// Parse errors are reported against the path given.
+    pub fn read<R: BufRead>(r: R, p: &Path) -> Result<Pattern> {
+        let mut tracks: Vec<(usize, String, Track, Comments)> = Vec::new();
+        let mut time_signature = None;
+        let mut swing = None;
+        let mut header = Vec::new();
+        let mut pending = Vec::new();
+        for (n, l) in r.lines().enumerate() {
+            let (n, l) = (n + 1, l?);
+            if let Some(w) = comment::within_first_word(&l) {
+                let reason = format!("`{}` in instrument `{}`", COMMENT, w);
+                return Err(ParseError(Diagnostic::new(
+                    p,
+                    n,
+                    &l,
+                    span_of(&l, w),
+                    reason,
+                )));
+            }
+            let (content, comment) = comment::split(&l[..]);
+            let comment = comment.map(String::from);
+
+            if content.trim().is_empty() {
+                match comment {
+                    Some(c) => pending.push(c),
+                    // comments set apart from the tracks by a blank line
+                    // belong to the whole file
+                    None if tracks.is_empty() => header.append(&mut pending),
                     None => (),
-                },
-                _ => return Err(ParseError(l)),
+                }
+                continue;
+            }
+            match parse_header(content) {
+                Ok((_, h)) => {
+                    // a single header of each kind may only precede the tracks
+                    let (name, duplicate) = match h {
+                        Header::TimeSignature(ts) => {
+                            ("time signature", time_signature.replace(ts).is_some())
+                        }
+                        Header::Swing(sw) => ("swing", swing.replace(sw).is_some()),
+                    };
+                    if duplicate || !tracks.is_empty() {
+                        let reason = if duplicate {
+                            format!("duplicate {}", name)
+                        } else {
+                            format!("the {} must precede the tracks", name)
+                        };
+                        let span = span_of(&l, content.trim());
+                        return Err(ParseError(Diagnostic::new(p, n, &l, span, reason)));
+                    }
+                    header.append(&mut pending);
+                    header.extend(comment);
+                    continue;
+                }
+                // not a header, so it may be a track
+                Err(nom::Err::Error(_)) => (),
+                Err(e) => {
+                    return Err(ParseError(Diagnostic::from_failure(
+                        p,
+                        n,
+                        &l,
+                        e,
+                        "invalid header",
+                    )))
+                }
             }
+            let beats = time_signature.unwrap_or_default().beats();
+            match parse_track(content, beats) {
+                Ok((_, t)) => {
+                    let comments = Comments {
+                        leading: pending.split_off(0),
+                        trailing: comment,
+                    };
+                    tracks.push((n, l, t, comments))
+                }
+                Err(e) => {
+                    return Err(ParseError(Diagnostic::from_failure(
+                        p,
+                        n,
+                        &l,
+                        e,
+                        "invalid track",
+                    )))
+                }
+            }
+        }
+
+        // the longest track determines the number of measures in the pattern
+        let steps = match tracks.first() {
+            Some((_, _, t, _)) => t.steps.len() / t.measures,
+            None => DEFAULT_STEPS_PER_MEASURE,
+        };
+        let measures = tracks
+            .iter()
+            .map(|(_, _, t, _)| t.measures)
+            .max()
+            .unwrap_or(1);
+
+        let mut m: IndexMap<Instrument, (Steps, Amplitude)> = IndexMap::new();
+        let mut cs: HashMap<Instrument, Comments> = HashMap::new();
+        for (n, l, t, c) in tracks {
+            // every measure must have as many steps as the first track's,
+            // shorter tracks must fit evenly into the pattern, and each
+            // instrument may only appear once
+            let (span, reason) = if t.steps.len() != steps * t.measures {
+                let len = t.steps.len() / t.measures;
+                let reason = format!("{} steps per measure, expected {}", len, steps);
+                (t.span, reason)
+            } else if measures % t.measures != 0 {
+                let reason = format!(
+                    "{} measures, expected a divisor of {}",
+                    t.measures, measures
+                );
+                (t.span, reason)
+            } else if let Entry::Vacant(e) = m.entry(t.instrument.clone()) {
+                e.insert((t.steps.repeat(measures / t.measures), t.amplitude));
+                cs.insert(t.instrument, c);
+                continue;
+            } else {
+                let span = l.split_whitespace().next().map(|i| span_of(&l, i));
+                let reason = format!("duplicate instrument `{}`", t.instrument);
+                (span.unwrap_or_default(), reason)
+            };
+            return Err(ParseError(Diagnostic::new(p, n, &l, span, reason)));
         }
 
-        Ok(Pattern(m))
+        Ok(Pattern {
+            tracks: m,
+            steps,
+            measures,
+            time_signature: time_signature.unwrap_or_default(),
+            swing,
+            comments: cs,
+            header,
+            footer: pending,
+        })
     }
 
     /// Returns the step sequence and amplitide associated with the instrument given.
     pub fn get(&self, i: &Instrument) -> Option<&(Steps, Amplitude)> {
-        self.0.get(i)
+        self.tracks.get(i)
+    }
+
+    /// Returns an iterator over the pattern's instruments in the order their
+    /// tracks were declared.
+    pub fn instruments(&self) -> Keys<'_, Instrument, (Steps, Amplitude)> {
+        self.tracks.keys()
+    }
+
+    /// Returns the number of steps in each of the pattern's measures.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the number of measures spanned by the pattern.
+    pub fn measures(&self) -> usize {
+        self.measures
+    }
+
+    /// Returns the pattern's time signature.
+    pub fn time_signature(&self) -> TimeSignature {
+        self.time_signature
     }
+
+    /// Returns the pattern's swing, if it specifies one.
+    pub fn swing(&self) -> Option<Swing> {
+        self.swing
+    }
+
+    /// Sets the pattern's time signature, unless the pattern spans several
+    /// measures whose steps do not divide into its beats.
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) -> Result<()> {
+        check_beats(self.steps, self.measures, time_signature)?;
+        self.time_signature = time_signature;
+
+        Ok(())
+    }
+
+    /// Sets or clears the pattern's swing.
+    pub fn set_swing(&mut self, swing: Option<Swing>) {
+        self.swing = swing;
+    }
+
+    /// Inserts a track for the instrument given and returns the track it
+    /// replaces, if any. The instrument's name must be one a pattern file can
+    /// hold: not empty, and without whitespace or the comment character.
+    /// The steps must span a whole number of measures. A track that spans fewer
+    /// measures than the pattern is repeated to fill it, and a track that spans
+    /// a multiple of the pattern's measures lengthens the pattern by repeating
+    /// the other tracks, as long as its steps divide into beats.
+    pub fn insert(
+        &mut self,
+        i: Instrument,
+        steps: Steps,
+        amplitude: Amplitude,
+    ) -> Result<Option<(Steps, Amplitude)>> {
+        check_instrument(&i)?;
+        let n = steps.len() / self.steps;
+        if steps.is_empty() || steps.len() % self.steps != 0 {
+            return Err(StepCountError(steps.len(), self.steps));
+        }
+
+        let steps = if n % self.measures == 0 {
+            check_beats(self.steps, n, self.time_signature)?;
+            let k = n / self.measures;
+            for (s, _) in self.tracks.values_mut() {
+                *s = s.repeat(k);
+            }
+            self.measures = n;
+            steps
+        } else if self.measures % n == 0 {
+            steps.repeat(self.measures / n)
+        } else {
+            return Err(StepCountError(steps.len(), self.steps));
+        };
+
+        Ok(self.tracks.insert(i, (steps, amplitude)))
+    }
+
+    /// Removes the track of the instrument given and returns it, if any.
+    pub fn remove(&mut self, i: &Instrument) -> Option<(Steps, Amplitude)> {
+        self.comments.remove(i);
+        self.tracks.shift_remove(i)
+    }
+
+    /// Renames the instrument of a track. The new name must be one a pattern
+    /// file can hold, as when inserting. Renaming an instrument to its own name
+    /// does nothing.
+    pub fn rename(&mut self, from: &Instrument, to: Instrument) -> Result<()> {
+        check_instrument(&to)?;
+        if *from == to && self.tracks.contains_key(from) {
+            return Ok(());
+        }
+        if self.tracks.contains_key(&to) {
+            return Err(DuplicateInstrumentError(to.to_string()));
+        }
+        if !self.tracks.contains_key(from) {
+            return Err(UnknownInstrumentError(from.to_string()));
+        }
+        if let Some(c) = self.comments.remove(from) {
+            self.comments.insert(to.clone(), c);
+        }
+        // keep the track in place
+        let tracks = self.tracks.drain(..);
+        self.tracks = tracks
+            .map(|(i, t)| if i == *from { (to.clone(), t) } else { (i, t) })
+            .collect();
+
+        Ok(())
+    }
+
+    /// Sets a step of the track of the instrument given to be played at the
+    /// velocity given, or silent. Steps are numbered from 0 across the
+    /// pattern's measures.
+    pub fn set_step(
+        &mut self,
+        i: &Instrument,
+        step: usize,
+        velocity: Option<Velocity>,
+    ) -> Result<()> {
+        let (steps, _) = self.track_mut(i)?;
+        if steps.set(step, velocity) {
+            Ok(())
+        } else {
+            Err(StepOutOfRangeError(step))
+        }
+    }
+
+    /// Toggles a step of the track of the instrument given between silent and
+    /// played at a regular velocity. Returns the new value of the step.
+    pub fn toggle_step(&mut self, i: &Instrument, step: usize) -> Result<Option<Velocity>> {
+        let (steps, _) = self.track_mut(i)?;
+        let velocity = match steps.get(step) {
+            Some(Some(_)) => None,
+            Some(None) => Some(Velocity::normal()),
+            None => return Err(StepOutOfRangeError(step)),
+        };
+        steps.set(step, velocity);
+
+        Ok(velocity)
+    }
+
+    /// Sets the amplitude of the track of the instrument given.
+    pub fn set_amplitude(&mut self, i: &Instrument, amplitude: Amplitude) -> Result<()> {
+        let (_, a) = self.track_mut(i)?;
+        *a = amplitude;
+
+        Ok(())
+    }
+
+    /// Returns the track of the instrument given for mutation.
+    fn track_mut(&mut self, i: &Instrument) -> Result<&mut (Steps, Amplitude)> {
+        self.tracks
+            .get_mut(i)
+            .ok_or_else(|| UnknownInstrumentError(i.to_string()))
+    }
+}
+
+impl Default for Pattern {
+    /// Returns an empty single measure pattern of 16 steps in 4/4 time.
+    fn default() -> Pattern {
+        // a single measure needs no beats to tell it apart
+        Pattern::new(DEFAULT_STEPS_PER_MEASURE, 1, TimeSignature::default()).unwrap()
+    }
+}
+
+/// Checks that an instrument can be written out as the first word of a track
+/// and read back, which is how `parse_instrument` reads it.
+fn check_instrument(i: &Instrument) -> Result<()> {
+    if i.0.is_empty() || i.0.contains(|c: char| c.is_whitespace() || c == COMMENT) {
+        return Err(InvalidInstrumentError(i.0.clone()));
+    }
+
+    Ok(())
+}
+
+/// Checks that a pattern of the shape given can be written out: measures are
+/// only told apart by their beats, so the steps of a pattern that spans
+/// several measures must divide into beats.
+fn check_beats(steps: usize, measures: usize, time_signature: TimeSignature) -> Result<()> {
+    let beats = time_signature.beats();
+    if measures > 1 && steps % beats != 0 {
+        return Err(BeatCountError(steps, beats));
+    }
+
+    Ok(())
 }
 
 impl fmt::Display for Pattern {
+    /// Writes the pattern in the pattern file format such that parsing the
+    /// output gives back the same pattern.
+    /// Tracks are kept in order, the instruments are padded to line up the
+    /// steps, and the steps are grouped into beats. A track at full volume
+    /// is written without an amplitude.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (i, (s, a)) in self.0.iter() {
-            writeln!(f, "{} {} {}", i, s, a)?;
+        for c in self.header.iter() {
+            writeln!(f, "{}{}", COMMENT, c)?;
+        }
+        writeln!(f, "{} {}", TIME_SIGNATURE, self.time_signature)?;
+        if let Some(swing) = self.swing {
+            writeln!(f, "{} {}", SWING, swing)?;
+        }
+
+        let width = self
+            .tracks
+            .keys()
+            .map(|i| i.0.chars().count())
+            .max()
+            .unwrap_or(0);
+        // steps that can't be divided into beats are written ungrouped, which
+        // only happens for single measure patterns
+        let beats = self.time_signature.beats();
+        let beat = if self.steps % beats == 0 {
+            self.steps / beats
+        } else {
+            self.steps * self.measures
+        };
+
+        for (i, (s, a)) in self.tracks.iter() {
+            let comments = self.comments.get(i);
+            for c in comments.iter().flat_map(|c| c.leading.iter()) {
+                writeln!(f, "{}{}", COMMENT, c)?;
+            }
+            write!(f, "{:width$} ", i.0, width = width)?;
+            for b in s.0.chunks(beat) {
+                write!(f, "{}{}", SEPARATOR, Steps(b.to_vec()))?;
+            }
+            write!(f, "{}", SEPARATOR)?;
+            if a.value() < Amplitude::max().value() {
+                write!(f, " {}", a)?;
+            }
+            if let Some(c) = comments.and_then(|c| c.trailing.as_ref()) {
+                write!(f, " {}{}", COMMENT, c)?;
+            }
+            writeln!(f)?;
+        }
+        for c in self.footer.iter() {
+            writeln!(f, "{}{}", COMMENT, c)?;
         }
 
         Ok(())
     }
 }
 
+/// Represents the comments attached to a track of a pattern file: the
+/// full-line comments that precede it and the comment that trails it.
+#[derive(Clone, Debug, Default)]
+struct Comments {
+    leading: Vec<String>,
+    trailing: Option<String>,
+}
+
+/// Represents the time signature of a pattern's measures.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimeSignature {
+    beats: usize,
+    unit: usize,
+}
+
+impl TimeSignature {
+    /// Returns a time signature with the number of beats given per measure and
+    /// the note value given per beat, or `None` if the beat unit is not a whole
+    /// note or one of its halvings (down to a 32nd note).
+    pub fn new(beats: usize, unit: usize) -> Option<TimeSignature> {
+        if beats > 0 && BEAT_UNITS.contains(&unit) {
+            Some(TimeSignature { beats, unit })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of beats in a measure.
+    pub fn beats(&self) -> usize {
+        self.beats
+    }
+
+    /// Returns the note value of a beat (e.g. 4 for a quarter note).
+    pub fn unit(&self) -> usize {
+        self.unit
+    }
+}
+
+impl Default for TimeSignature {
+    /// Returns common time (4/4).
+    fn default() -> TimeSignature {
+        TimeSignature { beats: 4, unit: 4 }
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.beats, self.unit)
+    }
+}
+
+/// Represents the amount by which off-beat steps are delayed as a percentage
+/// of a step in the range of [0,100).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Swing(f32);
+
+impl Swing {
+    /// Returns a swing of the percentage given, or `None` if the percentage is
+    /// outside of the range of [0,100).
+    pub fn new(percent: f32) -> Option<Swing> {
+        if (0.0..100.0).contains(&percent) {
+            Some(Swing(percent))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the delay of an off-beat step as a fraction of a step.
+    pub fn delay(&self) -> f32 {
+        self.0 / 100.0
+    }
+}
+
+impl FromStr for Swing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Swing> {
+        match all_consuming(parse_swing_amount)(s) {
+            Ok((_, percent)) => Swing::new(percent).ok_or_else(|| InvalidSwingError(s.into())),
+            _ => Err(InvalidSwingError(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for Swing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
 /// Represents a track's instrument name.
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Instrument(String);
 
 impl From<&str> for Instrument {
@@ -116,52 +698,161 @@ impl fmt::Display for Instrument {
 }
 
 /// The step sequence of a track.
-#[derive(Debug, PartialEq)]
-pub struct Steps(BitVec);
+/// Each step is either silent or played at a velocity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Steps(Vec<Option<Velocity>>);
 
 impl Steps {
-    /// Returns a seqence of all zeros.
-    pub fn zeros() -> Steps {
-        Steps(bitvec![0; STEPS_PER_MEASURE])
+    /// Returns a seqence of all silent steps with the number of steps given.
+    pub fn zeros(len: usize) -> Steps {
+        Steps(vec![None; len])
+    }
+
+    /// Returns the number of steps in this sequence.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether this sequence has no steps.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns a sequence of this sequence played back to back the number of
+    /// times given.
+    pub fn repeat(&self, n: usize) -> Steps {
+        Steps(self.0.repeat(n))
     }
 
     /// Performs an in-place stepwise union of this sequence and the one given.
+    /// Steps played in both sequences keep the greater velocity.
     pub fn union(&mut self, other: &Steps) {
-        self.0 |= other.0.clone();
+        for (s, o) in self.0.iter_mut().zip(other.0.iter()) {
+            if *o > *s {
+                *s = *o;
+            }
+        }
     }
 
-    /// Returns an immutable iterator over the step values.
-    pub fn iter(&self) -> Iter<LocalBits, usize> {
-        self.0.iter()
+    /// Returns the value of the step given, or `None` if it is out of range.
+    pub fn get(&self, i: usize) -> Option<Option<Velocity>> {
+        self.0.get(i).copied()
     }
 
-    /// Returns the number of silent steps at the end of this sequence.
-    pub fn trailing_silent_steps(&self) -> usize {
-        match self.0.iter().rposition(|s| *s) {
-            Some(n) => STEPS_PER_MEASURE - (n + 1),
-            None => 0,
+    /// Sets the value of the step given.
+    /// Returns false if the step is out of range.
+    pub fn set(&mut self, i: usize, velocity: Option<Velocity>) -> bool {
+        match self.0.get_mut(i) {
+            Some(s) => {
+                *s = velocity;
+                true
+            }
+            None => false,
         }
     }
+
+    /// Returns an immutable iterator over the step values.
+    pub fn iter(&self) -> Iter<'_, Option<Velocity>> {
+        self.0.iter()
+    }
 }
 
-impl From<BitVec> for Steps {
+impl From<Vec<Option<Velocity>>> for Steps {
     #[inline]
-    fn from(bs: BitVec) -> Steps {
-        Steps(bs)
+    fn from(vs: Vec<Option<Velocity>>) -> Steps {
+        Steps(vs)
     }
 }
 
 impl fmt::Display for Steps {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        for s in self.0.iter() {
+            match s {
+                Some(v) => write!(f, "{}", v)?,
+                None => write!(f, "{}", STEP_SILENT)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents the velocity of a played step in the range of (0,1] inclusive,
+/// where 1 is a regular step, or of an accented step above it.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Velocity(f32);
+
+impl Velocity {
+    /// Returns a velocity of the value given, or `None` if the value is outside
+    /// of the range of (0,1] of steps up to a regular step.
+    /// A pattern file only writes the velocities of regular steps and ghost
+    /// notes exactly, so any other is saved as the nearest of the digits 1 to 9
+    /// (i.e. rounded to ninths of a regular step).
+    pub fn new(v: f32) -> Option<Velocity> {
+        if v > 0.0 && v <= 1.0 {
+            Some(Velocity(v))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the velocity of an accented step.
+    pub fn accent() -> Velocity {
+        Velocity(VELOCITY_ACCENT)
+    }
+
+    /// Returns the velocity of a regular step.
+    pub fn normal() -> Velocity {
+        Velocity(VELOCITY_NORMAL)
+    }
+
+    /// Returns the velocity of a ghost note.
+    pub fn ghost() -> Velocity {
+        Velocity(VELOCITY_GHOST)
+    }
+
+    /// Returns the velocity of a step written as a digit from 1 to 9, where 9
+    /// is as loud as a regular step.
+    fn from_digit(d: u32) -> Velocity {
+        Velocity(d as f32 / 9.0)
+    }
+
+    /// Returns the velocity's value.
+    pub fn value(&self) -> f32 {
+        self.0
+    }
+}
+
+impl fmt::Display for Velocity {
+    /// Writes the velocity as a step of a pattern file.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == Velocity::accent() {
+            write!(f, "{}", STEP_ACCENT)
+        } else if *self == Velocity::normal() {
+            write!(f, "{}", STEP_PLAY)
+        } else if *self == Velocity::ghost() {
+            write!(f, "{}", STEP_GHOST)
+        } else {
+            write!(f, "{}", (self.0 * 9.0).round().max(1.0))
+        }
     }
 }
 
 /// Represents a track's amplitude in the range of [0,1] inclusive.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Amplitude(f32);
 
 impl Amplitude {
+    /// Returns an amplitude of the value given, or `None` if the value is
+    /// outside of the range of [0,1].
+    pub fn new(v: f32) -> Option<Amplitude> {
+        if (0.0..=1.0).contains(&v) {
+            Some(Amplitude(v))
+        } else {
+            None
+        }
+    }
+
     /// Returns an amplitude of the maximum value.
     pub fn max() -> Amplitude {
         Amplitude(1.0)
@@ -182,80 +873,557 @@ impl Amplitude {
     }
 }
 
+impl FromStr for Amplitude {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Amplitude> {
+        s.parse()
+            .ok()
+            .and_then(Amplitude::new)
+            .ok_or_else(|| InvalidAmplitudeError(s.into()))
+    }
+}
+
 impl fmt::Display for Amplitude {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-/// A type that represents a track in a pattern file.
-type Track = (Instrument, Steps, Amplitude);
+/// Represents a track line of a pattern file.
+#[derive(Debug)]
+struct Track {
+    instrument: Instrument,
+    steps: Steps,
+    /// The number of measures the steps span.
+    measures: usize,
+    amplitude: Amplitude,
+    /// The byte range of the steps within the line.
+    span: Range<usize>,
+}
+
+/// Represents a header line of a pattern file.
+#[derive(Debug, PartialEq)]
+enum Header {
+    TimeSignature(TimeSignature),
+    Swing(Swing),
+}
+
+/// Parses a header from a single line of a pattern file.
+/// Lines that are not headers fail with a recoverable error.
+fn parse_header(s: &str) -> ParseResult<'_, Header> {
+    alt((
+        map(parse_time_signature, Header::TimeSignature),
+        map(parse_swing, Header::Swing),
+    ))(s)
+}
+
+/// Parses the time signature header from a single line of a pattern file.
+fn parse_time_signature(s: &str) -> ParseResult<'_, TimeSignature> {
+    let (s, _) = space0(s)?;
+    let (s, _) = tag(TIME_SIGNATURE)(s)?;
+    let (s, _) = space1(s)?;
+    let (s, (span, (beats, unit))) = consumed(separated_pair(digit1, char('/'), digit1))(s)?;
+    let time_signature = match (beats.parse(), unit.parse()) {
+        (Ok(0), _) => return fail(beats, "0 beats, expected at least 1"),
+        (Ok(b), Ok(u)) => match TimeSignature::new(b, u) {
+            Some(ts) => ts,
+            None => {
+                let reason = format!("beat unit {}, expected one of {:?}", unit, BEAT_UNITS);
+                return fail(unit, reason);
+            }
+        },
+        _ => return fail(span, "time signature out of range"),
+    };
+    let (s, _) = parse_end(s, "time signature")?;
+
+    Ok((s, time_signature))
+}
 
-/// Parses a track from a single line of a pattern file.
-fn parse_track(s: &str) -> IResult<&str, Track> {
+/// Parses the swing header from a single line of a pattern file.
+fn parse_swing(s: &str) -> ParseResult<'_, Swing> {
     let (s, _) = space0(s)?;
+    let (s, _) = tag(SWING)(s)?;
+    let (s, _) = space1(s)?;
+    let (s, (span, percent)) = consumed(parse_swing_amount)(s)?;
+    // anything else may be a track whose instrument is named swing
+    let (s, _) = all_consuming(space0)(s)?;
+
+    match Swing::new(percent) {
+        Some(swing) => Ok((s, swing)),
+        None => {
+            let reason = format!("swing {}% outside [0,100)", span.trim_end_matches('%'));
+            fail(span, reason)
+        }
+    }
+}
+
+/// Parses a swing percentage with an optional percent sign.
+fn parse_swing_amount(s: &str) -> ParseResult<'_, f32> {
+    terminated(float, opt(char('%')))(s)
+}
+
+/// Parses a track from a single line of a pattern file whose measures have
+/// the number of beats given.
+fn parse_track(line: &str, beats: usize) -> ParseResult<'_, Track> {
+    let (s, _) = space0(line)?;
     let (s, instrument) = parse_instrument(s)?;
     let (s, _) = space0(s)?;
-    let (s, steps) = parse_steps(s)?;
+    let (s, (span, (steps, measures))) = consumed(|s| parse_steps(s, beats))(s)?;
     let (s, _) = space0(s)?;
     let (s, amplitude) = parse_amplitude(s)?;
-    let (s, _) = all_consuming(space0)(s)?;
+    let item = if amplitude.is_some() {
+        "amplitude"
+    } else {
+        "steps"
+    };
+    let (s, _) = parse_end(s, item)?;
 
     Ok((
         s,
-        (
-            Instrument::from(instrument),
-            Steps(steps),
-            Amplitude::defaulting(amplitude),
-        ),
+        Track {
+            instrument: Instrument::from(instrument),
+            steps: Steps(steps),
+            measures,
+            amplitude: Amplitude::defaulting(amplitude),
+            span: span_of(line, span),
+        },
     ))
 }
 
 /// Parses the instrument from a track line.
-fn parse_instrument(s: &str) -> IResult<&str, &str> {
+fn parse_instrument(s: &str) -> ParseResult<'_, &str> {
     is_not(" \t")(s)
 }
 
-/// Parses the steps from a track line.
-fn parse_steps(s: &str) -> IResult<&str, BitVec> {
-    let p = fold_many1(
-        alt((tag(STEP_PLAY), tag(STEP_SILENT), tag(SEPARATOR))),
-        || BitVec::with_capacity(STEPS_PER_MEASURE),
-        |mut acc: BitVec, i| {
-            match i {
-                STEP_PLAY => acc.push(true),
-                STEP_SILENT => acc.push(false),
-                _ => (),
-            }
-            acc
-        },
-    );
+/// Parses the steps from a track line along with the number of measures they span.
+/// If the steps are grouped by separators, there must be one group of steps
+/// per beat of each measure and every group must have the same number of steps.
+/// Ungrouped steps span a single measure.
+fn parse_steps(s: &str, beats: usize) -> ParseResult<'_, (Vec<Option<Velocity>>, usize)> {
+    let p = many0(alt((map(parse_step, Some), value(None, char(SEPARATOR)))));
+    let (s, (span, steps)) = consumed(p)(s)?;
+
+    // the steps must be set apart from whatever follows them
+    if let Some(c) = s.chars().next().filter(|c| !c.is_whitespace()) {
+        return fail(&s[..c.len_utf8()], format!("invalid step `{}`", c));
+    }
+    // drop the separators
+    let steps: Vec<Option<Velocity>> = steps.into_iter().flatten().collect();
+    if steps.is_empty() {
+        return fail(span, "missing steps");
+    }
+
+    // every step is a single character
+    let groups: Vec<&str> = span.split(SEPARATOR).filter(|g| !g.is_empty()).collect();
+    if let Some(g) = groups.iter().find(|g| g.len() != groups[0].len()) {
+        let reason = format!("beat of {} steps, expected {}", g.len(), groups[0].len());
+        return fail(g, reason);
+    }
+    let measures = if groups.len() == 1 {
+        1
+    } else if groups.len() % beats == 0 {
+        groups.len() / beats
+    } else if groups.len() < beats {
+        return fail(span, format!("{} beats, expected {}", groups.len(), beats));
+    } else {
+        let reason = format!("{} beats, expected a multiple of {}", groups.len(), beats);
+        return fail(span, reason);
+    };
+
+    Ok((s, (steps, measures)))
+}
 
-    verify(p, |v: &BitVec| v.len() == STEPS_PER_MEASURE)(s)
+/// Parses a single step from a track line.
+fn parse_step(s: &str) -> ParseResult<'_, Option<Velocity>> {
+    alt((
+        value(Some(Velocity::normal()), char(STEP_PLAY)),
+        value(Some(Velocity::accent()), char(STEP_ACCENT)),
+        value(Some(Velocity::ghost()), char(STEP_GHOST)),
+        value(None, char(STEP_SILENT)),
+        map(satisfy(|c| ('1'..='9').contains(&c)), |c| {
+            c.to_digit(10).map(Velocity::from_digit)
+        }),
+    ))(s)
 }
 
 /// Parses the amplitude from a track line.
-fn parse_amplitude(s: &str) -> IResult<&str, Option<f32>> {
-    verify(opt(float), |o: &Option<f32>| match *o {
-        Some(v) => 0.0 <= v && v <= 1.0,
-        None => true,
-    })(s)
+fn parse_amplitude(s: &str) -> ParseResult<'_, Option<f32>> {
+    match consumed(float)(s) {
+        Ok((s, (_, v))) if (0.0..=1.0).contains(&v) => Ok((s, Some(v))),
+        Ok((_, (span, _))) => fail(span, format!("amplitude {} outside [0,1]", span)),
+        Err(nom::Err::Error(_)) => Ok((s, None)),
+        Err(e) => Err(e),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const X: Option<Velocity> = Some(Velocity(VELOCITY_NORMAL));
+    const O: Option<Velocity> = None;
+
+    #[test]
+    fn test_read_repeats() {
+        let s1 = "a |x---|----|----|----|x---|----|----|----|\n\
+                  b |x-x-|----|----|----|\n";
+        let s2 = "time 3/4\n\
+                  a |x-|--|--|x-|--|--|x-|--|--|\n\
+                  b x-x---\n";
+        let s3 = "a |x---|----|----|----|\n\
+                  b |----|----|----|----|----|----|----|x---|\n";
+        let (a, b) = (Instrument::from("a"), Instrument::from("b"));
+        let read = |s: &str| Pattern::read(s.as_bytes(), Path::new("test")).unwrap();
+
+        // shorter tracks are repeated to fill the pattern
+        let p1 = read(s1);
+        let b1 = [vec![X, O, X, O], vec![O; 12]].concat();
+        assert_eq!((p1.steps(), p1.measures()), (16, 2));
+        assert_eq!(p1.get(&b).unwrap().0, Steps(b1.repeat(2)));
+
+        let p2 = read(s2);
+        assert_eq!((p2.steps(), p2.measures()), (6, 3));
+        assert_eq!(p2.get(&b).unwrap().0, Steps([X, O, X, O, O, O].repeat(3)));
+
+        let p3 = read(s3);
+        let a3 = [vec![X], vec![O; 15]].concat();
+        assert_eq!((p3.steps(), p3.measures()), (16, 2));
+        assert_eq!(p3.get(&a).unwrap().0, Steps(a3.repeat(2)));
+        assert_eq!(p3.get(&b).unwrap().0.len(), 32);
+    }
+
+    #[test]
+    fn test_read_comments() {
+        let s = "# groove\n\
+                 \n\
+                 time 4/4 # common\n\
+                 # backbeat\n\
+                 snare |----|x---|----|x---| # loud\n\
+                 \n\
+                 # fin\n";
+        let p = Pattern::read(s.as_bytes(), Path::new("test")).unwrap();
+
+        assert_eq!(
+            p.to_string(),
+            "# groove\n\
+             # common\n\
+             time 4/4\n\
+             # backbeat\n\
+             snare |----|x---|----|x---| # loud\n\
+             # fin\n"
+        );
+    }
+
+    #[test]
+    fn test_read_blank_lines() {
+        let s = "\n  \nkick |x---|----|x---|----|\n\n";
+        let p = Pattern::read(s.as_bytes(), Path::new("test")).unwrap();
+
+        assert!(p.get(&Instrument::from("kick")).is_some());
+        assert_eq!(p.comments.len(), 1);
+        assert!(p.header.is_empty());
+        assert!(p.footer.is_empty());
+    }
+
+    #[test]
+    fn test_read_diagnostics() {
+        let cases = [
+            ("a |x---|\nb |x--|", 2, 3, "3 steps per measure, expected 4"),
+            (
+                "a |x---|----|----|----| 1.1",
+                1,
+                25,
+                "amplitude 1.1 outside [0,1]",
+            ),
+            (
+                "a |x---|----|---|----|",
+                1,
+                14,
+                "beat of 3 steps, expected 4",
+            ),
+            ("a |x---|----|----|", 1, 3, "3 beats, expected 4"),
+            ("a |x-y-|----|----|----|", 1, 6, "invalid step `y`"),
+            ("a", 1, 2, "missing steps"),
+            (
+                "time 3/5",
+                1,
+                8,
+                "beat unit 5, expected one of [1, 2, 4, 8, 16, 32]",
+            ),
+            ("swing 120%", 1, 7, "swing 120% outside [0,100)"),
+            (
+                "a |x---|\nswing 50%",
+                2,
+                1,
+                "the swing must precede the tracks",
+            ),
+            ("time 3/4\ntime 4/4", 2, 1, "duplicate time signature"),
+            ("hi#hat |x---|", 1, 1, "`#` in instrument `hi#hat`"),
+            ("a |x---|\n a |----|", 2, 2, "duplicate instrument `a`"),
+            (
+                "time 2/4\na |x-|x-|x-|x-|\nb |x-|x-|x-|x-|x-|x-|",
+                2,
+                3,
+                "2 measures, expected a divisor of 3",
+            ),
+        ];
+
+        for (s, line, column, reason) in cases.iter() {
+            match Pattern::read(s.as_bytes(), Path::new("test")) {
+                Err(ParseError(d)) => {
+                    let d = d.to_string();
+                    let location = format!("--> test:{}:{}", line, column);
+                    assert_eq!(d.lines().next(), Some(*reason));
+                    assert!(d.lines().nth(1).unwrap().ends_with(&location));
+                }
+                r => panic!("unexpected {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_step_count_diagnostics() {
+        // the reason and the caret under the steps of the offending track
+        let cases = [
+            (
+                "a |x---|----|----|----|x---|----|----|----|\nb |x--|---|---|---|",
+                "12 steps per measure, expected 16",
+                "  |   ^^^^^^^^^^^^^^^^^",
+            ),
+            (
+                "a |x---|\nb |x-|--|--|--|x-|--|--|--|",
+                "8 steps per measure, expected 4",
+                "  |   ^^^^^^^^^^^^^^^^^^^^^^^^^",
+            ),
+            (
+                "time 3/4\na |x-|--|--|x-|--|--|\nb  x-----x-x---",
+                "12 steps per measure, expected 6",
+                "  |    ^^^^^^^^^^^^",
+            ),
+        ];
+
+        for (s, reason, caret) in cases.iter() {
+            match Pattern::read(s.as_bytes(), Path::new("test")) {
+                Err(ParseError(d)) => {
+                    let d = d.to_string();
+                    assert_eq!(d.lines().next(), Some(*reason));
+                    assert_eq!(d.lines().last(), Some(*caret));
+                }
+                r => panic!("unexpected {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let s = "time 3/4\n\
+                 swing 20%\n\
+                 snare |--|x-|--|--|Xo|7-|\n\
+                 hi-hat |x-x-x-| 0.5\n\
+                 kick |x-|--|--|\n";
+        let p = Pattern::read(s.as_bytes(), Path::new("test")).unwrap();
+        let w = p.to_string();
+
+        assert_eq!(
+            w,
+            "time 3/4\n\
+             swing 20%\n\
+             snare  |--|x-|--|--|Xo|7-|\n\
+             hi-hat |x-|x-|x-|x-|x-|x-| 0.5\n\
+             kick   |x-|--|--|x-|--|--|\n"
+        );
+        assert_eq!(
+            Pattern::read(w.as_bytes(), Path::new("test"))
+                .unwrap()
+                .to_string(),
+            w
+        );
+
+        for e in fs::read_dir("assets/patterns").unwrap() {
+            let p = Pattern::parse(&e.unwrap().path()).unwrap();
+            let w = p.to_string();
+            let q = Pattern::read(w.as_bytes(), Path::new("test")).unwrap();
+
+            assert_eq!(q.to_string(), w);
+            assert_eq!((q.steps(), q.measures()), (p.steps(), p.measures()));
+        }
+
+        // steps that don't divide into beats are only written for a single measure
+        let ts = TimeSignature::default();
+        let mut p = Pattern::new(6, 1, ts).unwrap();
+        let a = Instrument::from("a");
+        p.insert(a.clone(), Steps(vec![X, O, O, X, O, O]), Amplitude::max())
+            .unwrap();
+        let w = p.to_string();
+        let q = Pattern::read(w.as_bytes(), Path::new("test")).unwrap();
+
+        assert_eq!(w, "time 4/4\na |x--x--|\n");
+        assert_eq!((q.steps(), q.measures()), (6, 1));
+        assert_eq!(q.get(&a), p.get(&a));
+        assert!(matches!(Pattern::new(6, 2, ts), Err(BeatCountError(6, 4))));
+        assert!(matches!(
+            p.insert(a, Steps(vec![O; 12]), Amplitude::max()),
+            Err(BeatCountError(6, 4))
+        ));
+        assert_eq!(p.measures(), 1);
+
+        let mut p = Pattern::new(8, 2, ts).unwrap();
+        assert!(matches!(
+            p.set_time_signature(TimeSignature::new(3, 4).unwrap()),
+            Err(BeatCountError(8, 3))
+        ));
+        assert_eq!(p.time_signature(), ts);
+
+        // names that would not read back are rejected, keeping the pattern as is
+        let mut p = Pattern::default();
+        let a = Instrument::from("a");
+        p.insert(a.clone(), Steps::zeros(16), Amplitude::max())
+            .unwrap();
+        let w = p.to_string();
+        for name in ["hi hat", "hi#hat", "hi\that", ""] {
+            assert!(matches!(
+                p.insert(Instrument::from(name), Steps::zeros(16), Amplitude::max()),
+                Err(InvalidInstrumentError(_))
+            ));
+            assert!(matches!(
+                p.rename(&a, Instrument::from(name)),
+                Err(InvalidInstrumentError(_))
+            ));
+        }
+        assert_eq!(p.to_string(), w);
+        assert_eq!(
+            Pattern::read(w.as_bytes(), Path::new("test"))
+                .unwrap()
+                .to_string(),
+            w
+        );
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut p = Pattern::new(4, 1, TimeSignature::default()).unwrap();
+        let (a, b) = (Instrument::from("a"), Instrument::from("b"));
+
+        assert!(matches!(
+            Pattern::new(0, 1, TimeSignature::default()),
+            Err(EmptyPatternError(0, 1))
+        ));
+        assert!(Pattern::new(4, 0, TimeSignature::default()).is_err());
+
+        assert!(p
+            .insert(a.clone(), Steps(vec![X, O, O, O]), Amplitude::max())
+            .unwrap()
+            .is_none());
+        assert!(p
+            .insert(b.clone(), Steps(vec![O; 8]), Amplitude::max())
+            .is_ok());
+        assert_eq!(p.measures(), 2);
+        assert_eq!(p.get(&a).unwrap().0, Steps(vec![X, O, O, O, X, O, O, O]));
+        assert_eq!(p.instruments().collect::<Vec<_>>(), vec![&a, &b]);
+        assert!(p
+            .insert(a.clone(), Steps(vec![O; 12]), Amplitude::max())
+            .is_err());
+        assert!(p
+            .insert(a.clone(), Steps(vec![O; 3]), Amplitude::max())
+            .is_err());
+        assert!(p
+            .insert(a, Steps(vec![O; 4]), Amplitude::max())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut p = Pattern::default();
+        let (a, b, c) = (
+            Instrument::from("a"),
+            Instrument::from("b"),
+            Instrument::from("c"),
+        );
+        p.insert(a.clone(), Steps::zeros(16), Amplitude::max())
+            .unwrap();
+        p.insert(c.clone(), Steps::zeros(16), Amplitude::max())
+            .unwrap();
+
+        assert_eq!(p.toggle_step(&a, 2).unwrap(), X);
+        assert!(p.set_step(&a, 3, Some(Velocity::ghost())).is_ok());
+        assert!(p.set_step(&a, 16, X).is_err());
+        assert!(p.set_amplitude(&a, Amplitude::new(0.5).unwrap()).is_ok());
+        assert!(p.rename(&a, c.clone()).is_err());
+        assert!(p.rename(&a, a.clone()).is_ok());
+        assert!(p.rename(&a, b.clone()).is_ok());
+        assert!(p.rename(&a, b.clone()).is_err());
+        assert!(p.toggle_step(&a, 0).is_err());
+        assert_eq!(p.instruments().collect::<Vec<_>>(), vec![&b, &c]);
+
+        let (steps, amplitude) = p.remove(&b).unwrap();
+        assert_eq!(steps.to_string(), "--xo------------");
+        assert_eq!(amplitude.value(), 0.5);
+        assert_eq!(p.instruments().collect::<Vec<_>>(), vec![&c]);
+
+        // other velocities are saved as the nearest digit
+        p.set_step(&c, 0, Velocity::new(0.5)).unwrap();
+        let q = Pattern::read(p.to_string().as_bytes(), Path::new("test")).unwrap();
+        assert_eq!(q.get(&c).unwrap().0.to_string(), "5---------------");
+        assert_eq!(
+            q.get(&c).unwrap().0.get(0),
+            Some(Some(Velocity::from_digit(5)))
+        );
+    }
+
     #[test]
     fn test_parse_track() {
         let s = "a |----|----|----|----|";
-        let p = parse_track(s).unwrap();
+        let p = parse_track(s, 4).unwrap();
         let r = p.0;
         let l = p.1;
 
         assert_eq!(r, "");
-        assert_eq!(l.0, Instrument::from("a"));
-        assert_eq!(l.1, Steps(bitvec![0; STEPS_PER_MEASURE]));
+        assert_eq!(l.instrument, Instrument::from("a"));
+        assert_eq!(l.steps, Steps(vec![O; 16]));
+        assert_eq!(l.measures, 1);
+        assert_eq!(l.span, 2..23);
+    }
+
+    #[test]
+    fn test_parse_time_signature() {
+        let s1 = "time 4/4";
+        let s2 = " time 7/8 ";
+        let s3 = "time 6/8 x";
+        let s4 = "time 0/4";
+        let s5 = "time 3/5";
+        let s6 = "time 3";
+
+        assert_eq!(
+            parse_time_signature(s1).unwrap(),
+            ("", TimeSignature { beats: 4, unit: 4 })
+        );
+        assert_eq!(
+            parse_time_signature(s2).unwrap(),
+            ("", TimeSignature { beats: 7, unit: 8 })
+        );
+        assert!(parse_time_signature(s3).is_err());
+        assert!(parse_time_signature(s4).is_err());
+        assert!(parse_time_signature(s5).is_err());
+        assert!(parse_time_signature(s6).is_err());
+    }
+
+    #[test]
+    fn test_parse_swing() {
+        let s1 = "swing 50";
+        let s2 = " swing 33.3% ";
+        let s3 = "swing 100%";
+        let s4 = "swing -10";
+        let s5 = "swing";
+
+        assert_eq!(parse_swing(s1).unwrap(), ("", Swing(50.0)));
+        assert_eq!(parse_swing(s2).unwrap(), ("", Swing(33.3)));
+        assert!(parse_swing(s3).is_err());
+        assert!(parse_swing(s4).is_err());
+        assert!(parse_swing(s5).is_err());
+        assert_eq!("25%".parse::<Swing>().unwrap(), Swing(25.0));
+        assert!("25%%".parse::<Swing>().is_err());
     }
 
     #[test]
@@ -278,27 +1446,70 @@ mod tests {
     #[test]
     fn test_parse_steps() {
         let s1 = "";
-        let s2 = "|----|";
-        let s3 = "|----|----|----|----|-";
+        let s2 = "||";
+        let s3 = "|----|";
         let s4 = "|----|----|----|----|";
         let s5 = "|xxxx|xxxx|xxxx|xxxx|";
         let s6 = "|x-x-|x-x-|x-x-|x-x-|";
+        let s7 = "|x--|x--|x--|x--|";
+        let s8 = "----------------";
+        let s9 = "|----|----|----|----|-";
+        let s10 = "|----|----|----|";
+        let s11 = "|----|----|----|---|";
+        let s12 = "|----|----|----|----|----|----|----|----|";
 
-        assert!(parse_steps(s1).is_err());
-        assert!(parse_steps(s2).is_err());
-        assert!(parse_steps(s3).is_err());
+        assert!(parse_steps(s1, 4).is_err());
+        assert!(parse_steps(s2, 4).is_err());
+        assert_eq!(parse_steps(s3, 4).unwrap(), ("", (vec![O; 4], 1)));
+        assert_eq!(parse_steps(s4, 4).unwrap(), ("", (vec![O; 16], 1)));
+        assert_eq!(parse_steps(s5, 4).unwrap(), ("", (vec![X; 16], 1)));
         assert_eq!(
-            parse_steps(s4).unwrap(),
-            ("", bitvec![0; STEPS_PER_MEASURE])
+            parse_steps(s6, 4).unwrap(),
+            (
+                "",
+                (vec![X, O, X, O, X, O, X, O, X, O, X, O, X, O, X, O], 1)
+            )
         );
         assert_eq!(
-            parse_steps(s5).unwrap(),
-            ("", bitvec![1; STEPS_PER_MEASURE])
-        );
-        assert_eq!(
-            parse_steps(s6).unwrap(),
-            ("", bitvec![1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0])
+            parse_steps(s7, 4).unwrap(),
+            ("", (vec![X, O, O, X, O, O, X, O, O, X, O, O], 1))
         );
+        assert_eq!(parse_steps(s8, 4).unwrap(), ("", (vec![O; 16], 1)));
+        assert!(parse_steps(s9, 4).is_err());
+        assert!(parse_steps(s10, 4).is_err());
+        assert_eq!(parse_steps(s10, 3).unwrap(), ("", (vec![O; 12], 1)));
+        assert!(parse_steps(s11, 4).is_err());
+        assert_eq!(parse_steps(s12, 4).unwrap(), ("", (vec![O; 32], 2)));
+        assert_eq!(parse_steps(s12, 2).unwrap(), ("", (vec![O; 32], 4)));
+        assert!(parse_steps(s12, 3).is_err());
+    }
+
+    #[test]
+    fn test_parse_step() {
+        let s1 = "x";
+        let s2 = "X";
+        let s3 = "o";
+        let s4 = "-";
+        let s5 = "9";
+        let s6 = "3";
+        let s7 = "0";
+        let s8 = "|";
+
+        assert_eq!(parse_step(s1).unwrap(), ("", Some(Velocity::normal())));
+        assert_eq!(parse_step(s2).unwrap(), ("", Some(Velocity::accent())));
+        assert_eq!(parse_step(s3).unwrap(), ("", Some(Velocity::ghost())));
+        assert_eq!(parse_step(s4).unwrap(), ("", None));
+        assert_eq!(parse_step(s5).unwrap(), ("", Some(Velocity::normal())));
+        assert_eq!(parse_step(s6).unwrap(), ("", Some(Velocity(3.0 / 9.0))));
+        assert!(parse_step(s7).is_err());
+        assert!(parse_step(s8).is_err());
+    }
+
+    #[test]
+    fn test_steps_display() {
+        let (_, (s, _)) = parse_steps("|x-X-|o-3-|", 2).unwrap();
+
+        assert_eq!(Steps(s).to_string(), "x-X-o-3-");
     }
 
     #[test]
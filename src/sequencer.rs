@@ -0,0 +1,802 @@
+use rodio::{source::UniformSourceIterator, Source};
+use std::{
+    collections::hash_map::RandomState,
+    f32::consts::PI,
+    fs::File,
+    hash::{BuildHasher, Hasher},
+    io::BufReader,
+    iter,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::error::Result;
+
+/// Time over which a choked voice fades out.
+const CHOKE_FADE: Duration = Duration::from_millis(5);
+
+/// Represents an audio sample decoded into memory.
+///
+/// The samples are interleaved frames at the channel count and sample rate the
+/// buffer was decoded with.
+#[derive(Debug)]
+pub struct Buffer(Vec<f32>);
+
+impl Buffer {
+    /// Decodes the audio file located at the path given into a buffer with the
+    /// channel count and sample rate given.
+    pub fn decode(p: &Path, channels: u16, sample_rate: u32) -> Result<Buffer> {
+        let file = File::open(p)?;
+        let decoder = rodio::Decoder::new(BufReader::new(file))?;
+        let samples = UniformSourceIterator::<_, f32>::new(decoder, channels, sample_rate);
+
+        Ok(Buffer(samples.collect()))
+    }
+
+    /// Synthesises a sine tone of the frequency given that decays to silence
+    /// over the duration given, with the channel count and sample rate given.
+    pub fn tone(frequency: f32, duration: Duration, channels: u16, sample_rate: u32) -> Buffer {
+        let frames = (duration.as_secs_f32() * sample_rate as f32).round() as usize;
+        let samples = (0..frames).flat_map(|n| {
+            let t = n as f32 / sample_rate as f32;
+            let envelope = (1.0 - n as f32 / frames as f32).powi(2);
+            let v = (2.0 * PI * frequency * t).sin() * envelope;
+            iter::repeat(v).take(channels as usize)
+        });
+
+        Buffer(samples.collect())
+    }
+
+    /// Returns the buffer resampled to be played back at the rate given, where
+    /// a rate of 2 plays an octave higher in half the time. Samples between
+    /// frames are interpolated linearly.
+    pub fn resample(&self, rate: f32, channels: u16) -> Buffer {
+        let channels = channels as usize;
+        let frames = self.0.len() / channels;
+        let length = (frames as f64 / rate as f64).ceil() as usize;
+        let samples = (0..length).flat_map(|n| {
+            let position = n as f64 * rate as f64;
+            let frame = position as usize;
+            let fraction = (position - frame as f64) as f32;
+            (0..channels).map(move |c| {
+                let a = self.0[frame * channels + c];
+                let b = self.0.get((frame + 1) * channels + c).unwrap_or(&a);
+                a + (b - a) * fraction
+            })
+        });
+
+        Buffer(samples.collect())
+    }
+
+    /// Returns the frames of the buffer from the frame offset given up to the
+    /// end frame offset given, if any, or up to the end of the buffer.
+    pub fn trim(&self, start: usize, end: Option<usize>, channels: u16) -> Buffer {
+        let channels = channels as usize;
+        let frames = self.0.len() / channels;
+        let end = end.unwrap_or(frames).min(frames);
+        let start = start.min(end);
+
+        Buffer(self.0[start * channels..end * channels].to_vec())
+    }
+
+    /// Returns the buffer with its frames in reverse order.
+    pub fn reverse(&self, channels: u16) -> Buffer {
+        let frames = self.0.chunks(channels as usize).rev();
+
+        Buffer(frames.flatten().copied().collect())
+    }
+
+    /// Returns the buffer faded in over the number of frames given as attack,
+    /// decaying by 60 dB over the number of frames given as decay after the
+    /// attack, and faded out over the number of frames given as release before
+    /// its end. A decay of 0 does not decay.
+    pub fn envelope(&self, attack: usize, decay: usize, release: usize, channels: u16) -> Buffer {
+        let channels = channels as usize;
+        let frames = self.0.len() / channels;
+        let samples = self.0.chunks(channels).enumerate().flat_map(|(n, frame)| {
+            let mut gain = 1.0;
+            if n < attack {
+                gain *= n as f32 / attack as f32;
+            } else if decay > 0 {
+                gain *= 10f32.powf(-3.0 * (n - attack) as f32 / decay as f32);
+            }
+            let remaining = frames - n - 1;
+            if remaining < release {
+                gain *= remaining as f32 / release as f32;
+            }
+            frame.iter().map(move |s| s * gain)
+        });
+
+        Buffer(samples.collect())
+    }
+}
+
+impl From<Vec<f32>> for Buffer {
+    #[inline]
+    fn from(v: Vec<f32>) -> Buffer {
+        Buffer(v)
+    }
+}
+
+/// Represents a set of buffers that take turns at being played, either in the
+/// order given or at random without the same buffer twice in a row.
+///
+/// Clones share their turns, so the hits of a rotation keep taking turns as
+/// their schedules loop.
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    buffers: Arc<[Arc<Buffer>]>,
+    random: bool,
+    /// The number of turns taken, or the state of the random picks.
+    turn: Arc<AtomicU64>,
+    /// The index of the buffer last played, once any is.
+    last: Arc<AtomicUsize>,
+}
+
+impl Rotation {
+    /// Returns a rotation of the buffers given, which must not be empty.
+    /// Random picks are seeded afresh every time, so they differ between runs.
+    pub fn new(buffers: Vec<Arc<Buffer>>, random: bool) -> Rotation {
+        let turn = if random { seed() } else { 0 };
+
+        Rotation::starting_at(buffers, random, turn)
+    }
+
+    /// Returns a rotation of the buffers given that starts at the turn given.
+    fn starting_at(buffers: Vec<Arc<Buffer>>, random: bool, turn: u64) -> Rotation {
+        assert!(!buffers.is_empty(), "a rotation needs buffers");
+
+        Rotation {
+            buffers: buffers.into(),
+            random,
+            turn: Arc::new(AtomicU64::new(turn)),
+            last: Arc::new(AtomicUsize::new(usize::MAX)),
+        }
+    }
+
+    /// Returns the buffer whose turn it is.
+    pub(crate) fn next(&self) -> Arc<Buffer> {
+        let n = self.buffers.len();
+        let turn = self.turn.load(Ordering::Relaxed);
+        let i = if n == 1 {
+            0
+        } else if self.random {
+            // xorshift
+            let mut x = turn;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            self.turn.store(x, Ordering::Relaxed);
+
+            let last = self.last.load(Ordering::Relaxed);
+            if last < n {
+                // skip over the last pick so it never repeats
+                let i = (x % (n as u64 - 1)) as usize;
+                if i >= last {
+                    i + 1
+                } else {
+                    i
+                }
+            } else {
+                (x % n as u64) as usize
+            }
+        } else {
+            self.turn.store(turn + 1, Ordering::Relaxed);
+            (turn % n as u64) as usize
+        };
+        self.last.store(i, Ordering::Relaxed);
+
+        self.buffers[i].clone()
+    }
+}
+
+/// Returns a random, non-zero initial state for the picks of a rotation, drawn
+/// from the hasher keys the standard library seeds from the operating system
+/// and from the current time.
+fn seed() -> u64 {
+    let mut h = RandomState::new().build_hasher();
+    if let Ok(d) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        h.write_u128(d.as_nanos());
+    }
+
+    // xorshift would be stuck at a state of zero
+    h.finish() | 1
+}
+
+impl From<Arc<Buffer>> for Rotation {
+    #[inline]
+    fn from(buffer: Arc<Buffer>) -> Rotation {
+        Rotation::new(vec![buffer], false)
+    }
+}
+
+/// Represents the triggering of a rotation of buffers at a frame offset.
+#[derive(Clone, Debug)]
+struct Hit {
+    frame: u64,
+    rotation: Rotation,
+    gain: f32,
+    pan: f32,
+    choke: Option<Arc<str>>,
+}
+
+/// Represents a single iteration of a pattern as a sequence of hits placed at
+/// exact frame offsets.
+#[derive(Debug)]
+pub struct Schedule {
+    length: u64,
+    hits: Vec<Hit>,
+}
+
+impl Schedule {
+    /// Returns an empty schedule that spans the number of frames given.
+    pub fn new(length: u64) -> Schedule {
+        Schedule {
+            length: length.max(1),
+            hits: Vec::new(),
+        }
+    }
+
+    /// Returns the number of frames the schedule spans.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Returns a copy of the schedule cut short at the number of frames given.
+    pub fn truncate(&self, length: u64) -> Schedule {
+        let length = length.clamp(1, self.length);
+        let hits = self.hits.iter().take_while(|h| h.frame < length);
+
+        Schedule {
+            length,
+            hits: hits.cloned().collect(),
+        }
+    }
+
+    /// Returns the schedule split into the number of equally long parts given,
+    /// each holding the hits that start within it.
+    pub fn split(&self, parts: usize) -> Vec<Schedule> {
+        let parts = parts.max(1) as u64;
+        let bounds: Vec<u64> = (0..=parts).map(|i| self.length * i / parts).collect();
+
+        bounds
+            .windows(2)
+            .map(|b| Schedule {
+                length: (b[1] - b[0]).max(1),
+                hits: self
+                    .hits
+                    .iter()
+                    .filter(|h| (b[0]..b[1]).contains(&h.frame))
+                    .map(|h| Hit {
+                        frame: h.frame - b[0],
+                        ..h.clone()
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Adds a hit of the buffer or rotation of buffers given at the frame
+    /// offset given, panned to the stereo position given in the range of
+    /// [-1,1]. A hit in a choke group cuts off the sounding hits of the same
+    /// group. Hits that fall outside of the schedule are ignored.
+    pub fn add(
+        &mut self,
+        frame: u64,
+        rotation: impl Into<Rotation>,
+        gain: f32,
+        pan: f32,
+        choke: Option<Arc<str>>,
+    ) {
+        if frame >= self.length {
+            return;
+        }
+        let i = self.hits.partition_point(|h| h.frame <= frame);
+        self.hits.insert(
+            i,
+            Hit {
+                frame,
+                rotation: rotation.into(),
+                gain,
+                pan,
+                choke,
+            },
+        );
+    }
+}
+
+/// A type that represents the schedules a sequencer plays back to back.
+pub type Schedules = Box<dyn Iterator<Item = Arc<Schedule>> + Send>;
+
+/// A type that represents a sequence of schedules waiting to replace the
+/// sequence of a live loop.
+pub type Slot = Arc<Mutex<Option<Vec<Arc<Schedule>>>>>;
+
+/// An endless loop of schedules whose sequence can be replaced while playing.
+///
+/// A sequence put in the loop's slot replaces the current one at the next
+/// schedule boundary, and the loop carries on from the same position in the
+/// new sequence. An empty sequence loops the rest given.
+pub struct Live {
+    slot: Slot,
+    sequence: Vec<Arc<Schedule>>,
+    position: usize,
+    rest: Arc<Schedule>,
+}
+
+impl Live {
+    /// Returns a loop of the sequence given that rests for the schedule given
+    /// while the sequence is empty.
+    pub fn new(sequence: Vec<Arc<Schedule>>, rest: Schedule) -> Live {
+        Live {
+            slot: Slot::default(),
+            sequence,
+            position: 0,
+            rest: Arc::new(rest),
+        }
+    }
+
+    /// Returns the slot that replaces the loop's sequence.
+    pub fn slot(&self) -> Slot {
+        self.slot.clone()
+    }
+}
+
+impl Iterator for Live {
+    type Item = Arc<Schedule>;
+
+    fn next(&mut self) -> Option<Arc<Schedule>> {
+        // never block playback on a pending swap
+        if let Ok(mut slot) = self.slot.try_lock() {
+            if let Some(sequence) = slot.take() {
+                self.sequence = sequence;
+            }
+        }
+        if self.sequence.is_empty() {
+            return Some(self.rest.clone());
+        }
+        self.position %= self.sequence.len();
+        let schedule = self.sequence[self.position].clone();
+        self.position += 1;
+
+        Some(schedule)
+    }
+}
+
+/// Represents the playhead of a sequencer as the number of schedules it has
+/// moved past and the frame it has reached within the current schedule.
+///
+/// The clock advances as the audio device consumes frames, so it can be read
+/// from another thread to follow playback.
+#[derive(Debug, Default)]
+pub struct Clock {
+    schedule: AtomicUsize,
+    frame: AtomicU64,
+}
+
+impl Clock {
+    /// Returns the index of the current schedule and the frame within it.
+    pub fn read(&self) -> (usize, u64) {
+        (
+            self.schedule.load(Ordering::Relaxed),
+            self.frame.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Represents a sounding buffer.
+struct Voice {
+    buffer: Arc<Buffer>,
+    position: usize,
+    /// The gains of the left and right channels, or of every channel twice
+    /// over when the output is not stereo.
+    gains: [f32; 2],
+    choke: Option<Arc<str>>,
+    /// The number of frames left before the voice is silenced, once choked.
+    release: Option<usize>,
+}
+
+impl Voice {
+    /// Returns a voice of the hit given for the channel count given.
+    ///
+    /// Stereo output follows a constant-power pan law, so a hit keeps the same
+    /// loudness wherever it is panned. Any other output ignores the pan.
+    fn new(hit: &Hit, channels: u16) -> Voice {
+        let gains = if channels == 2 {
+            let angle = (hit.pan.clamp(-1.0, 1.0) + 1.0) * PI / 4.0;
+            [hit.gain * angle.cos(), hit.gain * angle.sin()]
+        } else {
+            [hit.gain; 2]
+        };
+
+        Voice {
+            buffer: hit.rotation.next(),
+            position: 0,
+            gains,
+            choke: hit.choke.clone(),
+            release: None,
+        }
+    }
+}
+
+/// A source that plays schedules back to back.
+///
+/// Each hit starts sounding at its exact frame offset, and voices that are still
+/// sounding at the end of a schedule ring out over the next one, so loops are
+/// gapless at any tempo. Once the schedules are exhausted the remaining voices
+/// ring out and the source ends.
+pub struct Sequencer {
+    channels: u16,
+    sample_rate: u32,
+    schedules: Schedules,
+    current: Option<Arc<Schedule>>,
+    position: u64,
+    next_hit: usize,
+    voices: Vec<Voice>,
+    frame: Vec<f32>,
+    channel: usize,
+    clock: Arc<Clock>,
+    /// The number of frames over which a choked voice fades out.
+    fade: usize,
+}
+
+impl Sequencer {
+    /// Returns a sequencer that plays the schedules given with the channel count
+    /// and sample rate given.
+    pub fn new(schedules: Schedules, channels: u16, sample_rate: u32) -> Sequencer {
+        let mut schedules = schedules;
+        let current = schedules.next();
+
+        Sequencer {
+            channels,
+            sample_rate,
+            schedules,
+            current,
+            position: 0,
+            next_hit: 0,
+            voices: Vec::new(),
+            frame: vec![0.0; channels as usize],
+            channel: 0,
+            clock: Arc::default(),
+            fade: ((CHOKE_FADE.as_secs_f32() * sample_rate as f32).round() as usize).max(1),
+        }
+    }
+
+    /// Returns the clock that follows the sequencer's playhead.
+    pub fn clock(&self) -> Arc<Clock> {
+        self.clock.clone()
+    }
+
+    /// Mixes the next frame.
+    /// Returns false once the schedules are exhausted and all voices are silent.
+    fn advance(&mut self) -> bool {
+        // move on to the next schedule at the end of the current one
+        while let Some(schedule) = &self.current {
+            if self.position < schedule.length {
+                break;
+            }
+            self.current = self.schedules.next();
+            self.position = 0;
+            self.next_hit = 0;
+            self.clock.schedule.fetch_add(1, Ordering::Relaxed);
+        }
+        self.clock.frame.store(self.position, Ordering::Relaxed);
+
+        // trigger the hits that fall on this frame
+        if let Some(schedule) = &self.current {
+            while let Some(hit) = schedule.hits.get(self.next_hit) {
+                if hit.frame > self.position {
+                    break;
+                }
+                if let Some(group) = &hit.choke {
+                    let fade = self.fade;
+                    self.voices
+                        .iter_mut()
+                        .filter(|v| v.release.is_none() && v.choke.as_ref() == Some(group))
+                        .for_each(|v| v.release = Some(fade));
+                }
+                self.voices.push(Voice::new(hit, self.channels));
+                self.next_hit += 1;
+            }
+            self.position += 1;
+        } else if self.voices.is_empty() {
+            return false;
+        }
+
+        // mix the sounding voices
+        let channels = self.frame.len();
+        self.frame.iter_mut().for_each(|s| *s = 0.0);
+        for voice in self.voices.iter_mut() {
+            let mut gains = voice.gains;
+            if let Some(release) = voice.release.as_mut() {
+                *release = release.saturating_sub(1);
+                let fade = *release as f32 / self.fade as f32;
+                gains.iter_mut().for_each(|g| *g *= fade);
+            }
+            let samples = &voice.buffer.0;
+            for (c, s) in self.frame.iter_mut().enumerate() {
+                if let Some(v) = samples.get(voice.position + c) {
+                    *s += v * gains[c.min(1)];
+                }
+            }
+            voice.position += channels;
+        }
+        self.voices
+            .retain(|v| v.position < v.buffer.0.len() && v.release != Some(0));
+
+        true
+    }
+}
+
+impl Iterator for Sequencer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 && !self.advance() {
+            return None;
+        }
+        let sample = self.frame[self.channel];
+        self.channel = (self.channel + 1) % self.frame.len();
+
+        Some(sample)
+    }
+}
+
+impl Source for Sequencer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    fn click() -> Arc<Buffer> {
+        Arc::new(Buffer::from(vec![1.0]))
+    }
+
+    #[test]
+    fn test_buffer_tone() {
+        let b = Buffer::tone(440.0, Duration::from_millis(10), 2, 1_000);
+
+        assert_eq!(b.0.len(), 20);
+        assert_eq!(b.0[0], 0.0);
+        assert_eq!(b.0[2], b.0[3]);
+        assert!(b.0.iter().all(|v| v.abs() <= 1.0));
+    }
+
+    #[test]
+    fn test_buffer_trim() {
+        let b = Buffer::from(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        assert_eq!(b.trim(1, Some(2), 2).0, vec![2.0, 3.0]);
+        assert_eq!(b.trim(1, None, 1).0, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(b.trim(4, Some(9), 2).0, Vec::<f32>::new());
+    }
+
+    #[test]
+    fn test_buffer_reverse() {
+        let b = Buffer::from(vec![0.0, 1.0, 2.0, 3.0]);
+
+        assert_eq!(b.reverse(1).0, vec![3.0, 2.0, 1.0, 0.0]);
+        assert_eq!(b.reverse(2).0, vec![2.0, 3.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_buffer_envelope() {
+        let b = Buffer::from(vec![1.0; 8]);
+
+        assert_eq!(
+            b.envelope(2, 0, 2, 2).0,
+            vec![0.0, 0.0, 0.5, 0.5, 0.5, 0.5, 0.0, 0.0]
+        );
+        let d = b.envelope(0, 1, 0, 1).0;
+        assert_eq!(d[0], 1.0);
+        assert!((d[1] - 0.001).abs() < 1e-6);
+        assert_eq!(b.envelope(0, 0, 0, 1).0, b.0);
+    }
+
+    #[test]
+    fn test_buffer_resample() {
+        let b = Buffer::from(vec![0.0, 1.0, 2.0, 3.0]);
+
+        assert_eq!(b.resample(2.0, 1).0, vec![0.0, 2.0]);
+        assert_eq!(
+            b.resample(0.5, 1).0,
+            vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.0]
+        );
+        assert_eq!(b.resample(2.0, 2).0, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let buffers: Vec<Arc<Buffer>> = (0..3)
+            .map(|v| Arc::new(Buffer::from(vec![v as f32])))
+            .collect();
+        let picks = |r: &Rotation| (0..60).map(|_| r.next().0[0]).collect::<Vec<f32>>();
+
+        let cycle = Rotation::new(buffers.clone(), false);
+        assert_eq!(picks(&cycle)[..4], [0.0, 1.0, 2.0, 0.0]);
+        cycle.clone().next();
+        assert_eq!(cycle.next().0[0], 1.0);
+
+        let random = picks(&Rotation::new(buffers.clone(), true));
+        assert!(random.windows(2).all(|w| w[0] != w[1]));
+        assert!((0..3).all(|v| random.contains(&(v as f32))));
+
+        // the same seed gives the same picks
+        let turn = 0x9e37_79b9_7f4a_7c15;
+        assert_eq!(
+            picks(&Rotation::starting_at(buffers.clone(), true, turn)),
+            picks(&Rotation::starting_at(buffers, true, turn))
+        );
+        assert_ne!(seed(), 0);
+    }
+
+    #[test]
+    fn test_schedule_add() {
+        let mut s = Schedule::new(4);
+        s.add(2, click(), 1.0, 0.0, None);
+        s.add(0, click(), 1.0, 0.0, None);
+        s.add(4, click(), 1.0, 0.0, None);
+
+        assert_eq!(s.length, 4);
+        assert_eq!(
+            s.hits.iter().map(|h| h.frame).collect::<Vec<u64>>(),
+            vec![0, 2]
+        );
+    }
+
+    #[test]
+    fn test_schedule_truncate() {
+        let mut s = Schedule::new(4);
+        s.add(0, click(), 1.0, 0.0, None);
+        s.add(2, click(), 1.0, 0.0, None);
+        let t = s.truncate(2);
+
+        assert_eq!(t.length(), 2);
+        assert_eq!(t.hits.len(), 1);
+        assert_eq!(s.truncate(8).length(), 4);
+    }
+
+    #[test]
+    fn test_schedule_split() {
+        let mut s = Schedule::new(10);
+        s.add(0, click(), 1.0, 0.0, None);
+        s.add(4, click(), 1.0, 0.0, None);
+        s.add(5, click(), 1.0, 0.0, None);
+        let parts = s.split(3);
+
+        assert_eq!(
+            parts.iter().map(|p| p.length).collect::<Vec<u64>>(),
+            vec![3, 3, 4]
+        );
+        assert_eq!(
+            parts
+                .iter()
+                .map(|p| p.hits.iter().map(|h| h.frame).collect())
+                .collect::<Vec<Vec<u64>>>(),
+            vec![vec![0], vec![1, 2], vec![]]
+        );
+    }
+
+    #[test]
+    fn test_live() {
+        let a = Arc::new(Schedule::new(1));
+        let b = Arc::new(Schedule::new(2));
+        let c = Arc::new(Schedule::new(3));
+        let mut live = Live::new(vec![a.clone(), b.clone()], Schedule::new(4));
+        let slot = live.slot();
+
+        assert_eq!(live.next().map(|s| s.length), Some(1));
+        *slot.lock().unwrap() = Some(vec![c.clone(), a, c]);
+        assert_eq!(live.next().map(|s| s.length), Some(1));
+        assert_eq!(live.next().map(|s| s.length), Some(3));
+        assert_eq!(live.next().map(|s| s.length), Some(3));
+        *slot.lock().unwrap() = Some(vec![]);
+        assert_eq!(live.next().map(|s| s.length), Some(4));
+    }
+
+    #[test]
+    fn test_sequencer_repeat() {
+        let mut s = Schedule::new(3);
+        s.add(1, click(), 0.5, 0.0, None);
+        let s = Arc::new(s);
+        let q = Sequencer::new(Box::new(iter::repeat(s).take(2)), 1, 1);
+
+        assert_eq!(q.collect::<Vec<f32>>(), vec![0.0, 0.5, 0.0, 0.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_sequencer_clock() {
+        let schedules = vec![Arc::new(Schedule::new(2)), Arc::new(Schedule::new(3))];
+        let mut s = Sequencer::new(Box::new(schedules.into_iter()), 1, 1);
+        let clock = s.clock();
+
+        assert_eq!(clock.read(), (0, 0));
+        s.nth(2);
+        assert_eq!(clock.read(), (1, 0));
+        s.next();
+        assert_eq!(clock.read(), (1, 1));
+    }
+
+    #[test]
+    fn test_sequencer_ring_out() {
+        let mut s = Schedule::new(2);
+        s.add(
+            1,
+            Arc::new(Buffer::from(vec![1.0, 1.0, 1.0])),
+            1.0,
+            0.0,
+            None,
+        );
+        let q = Sequencer::new(Box::new(iter::once(Arc::new(s))), 1, 1);
+
+        assert_eq!(q.collect::<Vec<f32>>(), vec![0.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_sequencer_channels() {
+        let mut s = Schedule::new(2);
+        s.add(0, Arc::new(Buffer::from(vec![1.0, 0.5])), 1.0, -1.0, None);
+        let q = Sequencer::new(Box::new(iter::once(Arc::new(s))), 2, 1);
+
+        assert_eq!(q.collect::<Vec<f32>>(), vec![1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_sequencer_pan() {
+        let pan = |pan| {
+            let mut s = Schedule::new(1);
+            s.add(0, Arc::new(Buffer::from(vec![1.0, 1.0])), 0.5, pan, None);
+            Sequencer::new(Box::new(iter::once(Arc::new(s))), 2, 1).collect::<Vec<f32>>()
+        };
+
+        let centre = pan(0.0);
+        assert!((centre[0] - 0.5 * FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(centre[0], centre[1]);
+        let right = pan(0.5);
+        assert!(right[1] > right[0]);
+        assert!((right[0].powi(2) + right[1].powi(2) - 0.25).abs() < 1e-6);
+        assert!(pan(1.0)[0].abs() < 1e-6);
+
+        let mut s = Schedule::new(1);
+        s.add(0, click(), 0.5, 1.0, None);
+        let mono = Sequencer::new(Box::new(iter::once(Arc::new(s))), 1, 1);
+        assert_eq!(mono.collect::<Vec<f32>>(), vec![0.5]);
+    }
+
+    #[test]
+    fn test_sequencer_choke() {
+        let ring = || Arc::new(Buffer::from(vec![1.0; 8]));
+        let hats: Option<Arc<str>> = Some("hats".into());
+        let mut s = Schedule::new(8);
+        s.add(0, ring(), 1.0, 0.0, hats.clone());
+        s.add(0, ring(), 0.5, 0.0, None);
+        s.add(2, Arc::new(Buffer::from(vec![0.0])), 1.0, 0.0, hats);
+        let q = Sequencer::new(Box::new(iter::once(Arc::new(s))), 1, 1_000);
+
+        let expected = [1.5, 1.5, 1.3, 1.1, 0.9, 0.7, 0.5, 0.5];
+        let mix = q.collect::<Vec<f32>>();
+        assert_eq!(mix.len(), expected.len());
+        assert!(mix.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+}
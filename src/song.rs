@@ -0,0 +1,215 @@
+extern crate nom;
+
+use nom::{
+    bytes::complete::is_not,
+    character::complete::{char, digit1, space0},
+    combinator::opt,
+    sequence::preceded,
+};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    slice::Iter,
+};
+
+use crate::{
+    comment,
+    diagnostic::{fail, parse_end, span_of, Diagnostic, ParseResult},
+    error::{Error::*, Result},
+    pattern::Pattern,
+};
+
+/// Introduces the repeat count of a section in a song file.
+const REPEAT: char = 'x';
+
+/// Represents the contents of a song file.
+///
+/// A song file arranges pattern files into a sequence of sections that are
+/// played back to back. Each line of a song file contains the path of a pattern
+/// file, relative to the song file, and an optional repeat count (e.g. `x4`).
+/// By default, a section is played once. A pattern file may appear in any
+/// number of sections.
+/// Blank lines are ignored, and comments start with `#` and run to the end of
+/// the line.
+///
+/// # Example
+///
+/// This is an example of a song file's contents that plays an intro, a verse
+/// four times, a fill, and then a chorus eight times.
+///
+/// ```text
+/// intro  x1
+/// verse  x4
+/// fill
+/// chorus x8
+/// ```
+#[derive(Debug, Default)]
+pub struct Song {
+    sections: Vec<Section>,
+    /// The paths of the pattern files read, in the order first listed.
+    paths: Vec<PathBuf>,
+}
+
+/// A type that represents a section of a song: a pattern and the number of times
+/// it is played.
+pub type Section = (Pattern, usize);
+
+impl Song {
+    /// Returns an empty song.
+    pub fn new() -> Song {
+        Song::default()
+    }
+
+    /// Parses a song file located at the path given, along with the pattern
+    /// files it lists.
+    pub fn parse(p: &Path) -> Result<Song> {
+        if !p.is_file() {
+            return Err(FileDoesNotExistError(p.into()));
+        }
+        let f = File::open(p)?;
+
+        Song::read(BufReader::new(f), p)
+    }
+
+    /// Reads the contents of a song file from the reader given.
+    /// Pattern files are found relative to the directory of the path given, and
+    /// parse errors are reported against it.
+    pub fn read<R: BufRead>(r: R, p: &Path) -> Result<Song> {
+        let dir = p.parent().unwrap_or_else(|| Path::new(""));
+        let mut song = Song::new();
+        for (n, l) in r.lines().enumerate() {
+            let (n, l) = (n + 1, l?);
+            let (content, _) = comment::split(&l[..]);
+            if content.trim().is_empty() {
+                continue;
+            }
+            match parse_section(content) {
+                Ok((_, (file, repeats))) => {
+                    let path = dir.join(file);
+                    if !path.is_file() {
+                        let reason = format!("pattern file {} does not exist", path.display());
+                        let span = span_of(&l, file);
+                        return Err(ParseError(Diagnostic::new(p, n, &l, span, reason)));
+                    }
+                    song.push(Pattern::parse(&path)?, repeats);
+                    if !song.paths.contains(&path) {
+                        song.paths.push(path);
+                    }
+                }
+                Err(e) => {
+                    return Err(ParseError(Diagnostic::from_failure(
+                        p,
+                        n,
+                        &l,
+                        e,
+                        "invalid section",
+                    )))
+                }
+            }
+        }
+
+        Ok(song)
+    }
+
+    /// Appends a section that plays the pattern given the number of times given.
+    pub fn push(&mut self, pattern: Pattern, repeats: usize) {
+        self.sections.push((pattern, repeats));
+    }
+
+    /// Returns an iterator over the song's sections in the order they are played.
+    pub fn sections(&self) -> Iter<'_, Section> {
+        self.sections.iter()
+    }
+
+    /// Returns the paths of the pattern files the song was read from, in the
+    /// order they were first listed.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
+
+impl From<Pattern> for Song {
+    /// Returns a song that plays the pattern given once.
+    fn from(pattern: Pattern) -> Song {
+        Song {
+            sections: vec![(pattern, 1)],
+            paths: Vec::new(),
+        }
+    }
+}
+
+/// Parses a section from a single line of a song file.
+fn parse_section(s: &str) -> ParseResult<'_, (&str, usize)> {
+    let (s, _) = space0(s)?;
+    let (s, file) = is_not(" \t")(s)?;
+    let (s, _) = space0(s)?;
+    let (s, repeats) = opt(parse_repeats)(s)?;
+    let (s, _) = parse_end(s, "section")?;
+
+    Ok((s, (file, repeats.unwrap_or(1))))
+}
+
+/// Parses the repeat count of a section.
+fn parse_repeats(s: &str) -> ParseResult<'_, usize> {
+    let (rest, digits) = preceded(char(REPEAT), digit1)(s)?;
+    let span = &s[..s.len() - rest.len()];
+
+    match digits.parse() {
+        Ok(0) => fail(span, "0 repeats, expected at least 1"),
+        Ok(n) => Ok((rest, n)),
+        _ => fail(span, "repeat count out of range"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let s = "# song\n\
+                 standard x2\n\
+                 \n\
+                 thriller # chorus\n";
+        let song = Song::read(s.as_bytes(), Path::new("assets/patterns/song")).unwrap();
+        let repeats: Vec<usize> = song.sections().map(|(_, n)| *n).collect();
+
+        assert_eq!(repeats, vec![2, 1]);
+        assert_eq!(
+            song.paths(),
+            [
+                PathBuf::from("assets/patterns/standard"),
+                PathBuf::from("assets/patterns/thriller")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_diagnostics() {
+        let s = "standard\nmissing x2\n";
+
+        match Song::read(s.as_bytes(), Path::new("assets/patterns/song")) {
+            Err(ParseError(d)) => {
+                assert_eq!((d.line(), d.column()), (2, 1));
+                assert!(d.reason().ends_with("does not exist"));
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_parse_section() {
+        let s1 = "verse";
+        let s2 = " verse  x4 ";
+        let s3 = "verse x0";
+        let s4 = "verse 4";
+        let s5 = "verse x4 x2";
+
+        assert_eq!(parse_section(s1).unwrap(), ("", ("verse", 1)));
+        assert_eq!(parse_section(s2).unwrap(), ("", ("verse", 4)));
+        assert!(parse_section(s3).is_err());
+        assert!(parse_section(s4).is_err());
+        assert!(parse_section(s5).is_err());
+    }
+}